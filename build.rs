@@ -2,6 +2,8 @@ use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs::File;
 use std::path::Path;
+use std::thread;
+use std::time::Instant;
 
 use reliquary::resource::excel::{
     AvatarConfigMap, AvatarSkillTreeConfigMap, EquipmentConfigMap, MultiplePathAvatarConfigMap,
@@ -12,48 +14,91 @@ use ureq::serde::de::DeserializeOwned;
 use ureq::serde::Serialize;
 use ureq::serde_json::Value;
 
+#[path = "src/retry.rs"]
+mod retry;
+
 const BASE_RESOURCE_URL: &str = "https://gitlab.com/Dimbreath/turnbasedgamedata/-/raw/main";
 const KEY_URL: &str =
     "https://raw.githubusercontent.com/tamilpp25/Iridium-SR/refs/heads/main/data/Keys.json";
 
-macro_rules! download_config_and_store_text_hashes {
-    ($t:ty, $field:ident, $hashes:ident) => {
-        write_to_out(
-            {
-                let url = resource_url::<$t>();
-                let value = download_as_json::<$t>(&url);
-                for cfg in value.0.iter() {
-                    $hashes.insert(cfg.$field);
-                }
-                value
-            },
-            <$t>::get_json_name(),
-        )
-    };
+/// Only download a few text maps at once, to keep memory usage in check
+const PARALLEL_TEXT_MAPS: usize = 4;
+
+macro_rules! download_config_and_collect_text_hashes {
+    ($t:ty, $field:ident) => {{
+        let url = resource_url::<$t>();
+        let value = download_as_json::<$t>(&url);
+        let hashes: Vec<TextMapEntry> = value.0.iter().map(|cfg| cfg.$field).collect();
+        write_to_out(value, <$t>::get_json_name());
+        hashes
+    }};
 }
 
 fn main() {
     println!("cargo:rerun-if-changed=Cargo.toml");
     println!("cargo:rerun-if-changed=Cargo.lock");
 
+    // expose where the data was sourced from, for the build info
+    println!("cargo:rustc-env=RESOURCE_URL={BASE_RESOURCE_URL}");
+    println!("cargo:rustc-env=KEYS_URL={KEY_URL}");
+    println!(
+        "cargo:rustc-env=BUILD_TARGET={}",
+        env::var("TARGET").unwrap()
+    );
+
+    let started = Instant::now();
+
     // the text map is really, REALLY large (>25MB), so we're optimizing by only
     // keeping the entries used from relevant config files where the strings are required
     // for the export
-    let mut text_hashes: HashSet<TextMapEntry> = HashSet::new();
-
-    download_config_and_store_text_hashes!(AvatarConfigMap, AvatarName, text_hashes);
-    download_config_and_store_text_hashes!(EquipmentConfigMap, EquipmentName, text_hashes);
-    download_config_and_store_text_hashes!(RelicSetConfigMap, SetName, text_hashes);
+    //
+    // none of the configs depend on each other, so they're all downloaded at the same time.
+    // a failed download panics its thread with the url, which fails the whole scope
+    let text_hashes: HashSet<TextMapEntry> = thread::scope(|s| {
+        let text_hash_downloads = [
+            s.spawn(|| download_config_and_collect_text_hashes!(AvatarConfigMap, AvatarName)),
+            s.spawn(|| download_config_and_collect_text_hashes!(EquipmentConfigMap, EquipmentName)),
+            s.spawn(|| download_config_and_collect_text_hashes!(RelicSetConfigMap, SetName)),
+        ];
+
+        s.spawn(download_config::<AvatarSkillTreeConfigMap>);
+        s.spawn(download_config::<MultiplePathAvatarConfigMap>);
+        s.spawn(download_config::<RelicConfigMap>);
+        s.spawn(download_config::<RelicMainAffixConfigMap>);
+        s.spawn(download_config::<RelicSubAffixConfigMap>);
+
+        s.spawn(|| write_to_out(download_as_json::<Value>(KEY_URL), "keys.json"));
+
+        text_hash_downloads
+            .into_iter()
+            .flat_map(|download| download.join().unwrap())
+            .collect()
+    });
+
+    // the text maps can only be filtered once all hashes are known
+    for languages in text_map_languages().chunks(PARALLEL_TEXT_MAPS) {
+        thread::scope(|s| {
+            for language in languages {
+                let text_hashes = &text_hashes;
+                s.spawn(move || save_text_map(text_hashes, language));
+            }
+        });
+    }
+
+    println!("downloaded resources in {:.1?}", started.elapsed());
+}
 
-    download_config::<AvatarSkillTreeConfigMap>();
-    download_config::<MultiplePathAvatarConfigMap>();
-    download_config::<RelicConfigMap>();
-    download_config::<RelicMainAffixConfigMap>();
-    download_config::<RelicSubAffixConfigMap>();
+/// English is always bundled, other languages only if their `lang-*` feature is enabled
+fn text_map_languages() -> Vec<String> {
+    let mut languages = vec!["EN".to_owned()];
 
-    save_text_map(&text_hashes, "EN");
+    // cargo exposes enabled features as CARGO_FEATURE_<NAME>, e.g. CARGO_FEATURE_LANG_JP
+    languages.extend(
+        env::vars()
+            .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_LANG_").map(str::to_owned)),
+    );
 
-    write_to_out(download_as_json::<Value>(KEY_URL), "keys.json");
+    languages
 }
 
 fn save_text_map(hashes: &HashSet<TextMapEntry>, language: &str) {
@@ -83,7 +128,21 @@ fn resource_url<T: ResourceMap>() -> String {
 }
 
 fn download_as_json<T: DeserializeOwned>(url: &str) -> T {
-    ureq::get(url).call().unwrap().into_json().unwrap()
+    let response = retry::with_retry(
+        || ureq::get(url).call(),
+        |e, attempt, delay| {
+            println!("could not download {url} (attempt {attempt}): {e}, retrying in {delay:.1?}");
+        },
+    )
+    .unwrap_or_else(|e| panic!("could not download {url}: {e}"));
+
+    response.into_json().unwrap_or_else(|e| {
+        if e.kind() == std::io::ErrorKind::InvalidData {
+            panic!("could not parse {url}, the data format might have changed: {e}")
+        } else {
+            panic!("could not read response of {url}: {e}")
+        }
+    })
 }
 
 fn write_to_out<T: DeserializeOwned + Serialize>(value: T, file_name: &str) {
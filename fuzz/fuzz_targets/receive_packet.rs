@@ -0,0 +1,24 @@
+#![no_main]
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use libfuzzer_sys::fuzz_target;
+use reliquary::network::GameSniffer;
+use reliquary_archiver::export::database::{Database, Language};
+
+// loading the database is comparatively slow, only do it once per fuzzing process
+static KEYS: OnceLock<HashMap<u32, Vec<u8>>> = OnceLock::new();
+
+// feeds a sequence of arbitrary payloads through a single sniffer, the same way
+// captured packets are fed during a live or file capture. the sniffer keeps kcp state
+// across packets, so a sequence reaches a lot more code than a single payload would.
+// any panic is considered a bug, malformed input should only ever be dropped
+fuzz_target!(|packets: Vec<Vec<u8>>| {
+    let keys = KEYS.get_or_init(|| Database::new(Language::En).keys);
+    let mut sniffer = GameSniffer::new().set_initial_keys(keys.clone());
+
+    for packet in packets {
+        let _ = sniffer.receive_packet(packet);
+    }
+});
@@ -1,13 +1,86 @@
 use base64::prelude::BASE64_STANDARD;
-use base64::Engine;
+use base64::{DecodeError, Engine};
+use clap::ValueEnum;
 use reliquary::resource::excel::{
     AvatarConfigMap, AvatarSkillTreeConfigMap, EquipmentConfigMap, MultiplePathAvatarConfigMap,
     RelicConfigMap, RelicMainAffixConfigMap, RelicSetConfigMap, RelicSubAffixConfigMap,
 };
 use reliquary::resource::text_map::TextMap;
+use reliquary::resource::ResourceMap;
 use serde::de::DeserializeOwned;
 use std::collections::HashMap;
-use tracing::{info, instrument};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, instrument, warn};
+
+use crate::retry::with_retry;
+
+/// Language of the text map that names are looked up in. Only english is bundled by default,
+/// the others need their `lang-*` feature (or `all-languages`) or a `--database-dir`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Language {
+    Chs,
+    Cht,
+    De,
+    #[default]
+    En,
+    Es,
+    Fr,
+    Id,
+    Jp,
+    Kr,
+    Pt,
+    Ru,
+    Th,
+    Vi,
+}
+
+impl Language {
+    /// Suffix of the language's text map file, as in `TextMap{code}.json`
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::Chs => "CHS",
+            Language::Cht => "CHT",
+            Language::De => "DE",
+            Language::En => "EN",
+            Language::Es => "ES",
+            Language::Fr => "FR",
+            Language::Id => "ID",
+            Language::Jp => "JP",
+            Language::Kr => "KR",
+            Language::Pt => "PT",
+            Language::Ru => "RU",
+            Language::Th => "TH",
+            Language::Vi => "VI",
+        }
+    }
+
+    /// Languages whose text map is bundled into this build
+    pub fn compiled() -> Vec<Language> {
+        Language::value_variants()
+            .iter()
+            .copied()
+            .filter(|language| match language {
+                Language::Chs => cfg!(feature = "lang-chs"),
+                Language::Cht => cfg!(feature = "lang-cht"),
+                Language::De => cfg!(feature = "lang-de"),
+                Language::En => true,
+                Language::Es => cfg!(feature = "lang-es"),
+                Language::Fr => cfg!(feature = "lang-fr"),
+                Language::Id => cfg!(feature = "lang-id"),
+                Language::Jp => cfg!(feature = "lang-jp"),
+                Language::Kr => cfg!(feature = "lang-kr"),
+                Language::Pt => cfg!(feature = "lang-pt"),
+                Language::Ru => cfg!(feature = "lang-ru"),
+                Language::Th => cfg!(feature = "lang-th"),
+                Language::Vi => cfg!(feature = "lang-vi"),
+            })
+            .collect()
+    }
+}
 
 pub struct Database {
     pub avatar_config: AvatarConfigMap,
@@ -24,7 +97,7 @@ pub struct Database {
 
 impl Database {
     #[instrument(name = "config_map")]
-    pub fn new() -> Self {
+    pub fn new(language: Language) -> Self {
         info!("using local database");
 
         // config files are downloaded by the build script
@@ -66,25 +139,144 @@ impl Database {
                 env!("OUT_DIR"),
                 "/RelicSubAffixConfig.json"
             ))),
-            text_map: Self::parse_json(include_str!(concat!(env!("OUT_DIR"), "/TextMapEN.json"))),
+            text_map: Self::parse_json(Self::local_text_map(language).unwrap_or_else(|| {
+                warn!(
+                    language = language.code(),
+                    "text map is not bundled with this build, using english instead"
+                );
+                warn!("build with the matching lang-* feature, or load it with --database-dir");
+                Self::local_text_map(Language::En).unwrap()
+            })),
             keys: Self::load_local_keys(),
         }
     }
 
+    /// Load the database from a directory laid out like the resource repository,
+    /// i.e. `ExcelOutput/*.json` and `TextMap/TextMap{language}.json`, with `Keys.json`
+    /// at the top level
+    #[instrument(name = "config_map")]
+    pub fn new_from_directory(dir: &Path, language: Language) -> io::Result<Self> {
+        info!("using database from {}", dir.display());
+
+        let text_map_file = format!("TextMap{}.json", language.code());
+
+        Ok(Database {
+            avatar_config: Self::load_config(dir)?,
+            avatar_skill_tree_config: Self::load_config(dir)?,
+            equipment_config: Self::load_config(dir)?,
+            multipath_avatar_config: Self::load_config(dir)?,
+            relic_config: Self::load_config(dir)?,
+            relic_set_config: Self::load_config(dir)?,
+            relic_main_affix_config: Self::load_config(dir)?,
+            relic_sub_affix_config: Self::load_config(dir)?,
+            text_map: Self::load_json_file(&dir.join("TextMap").join(text_map_file))?,
+            keys: Self::decode_keys(Self::load_json_file(&dir.join("Keys.json"))?).map_err(
+                |e| io::Error::new(io::ErrorKind::InvalidData, format!("Keys.json: {e}")),
+            )?,
+        })
+    }
+
+    fn load_config<T: ResourceMap + DeserializeOwned>(dir: &Path) -> io::Result<T> {
+        Self::load_json_file(&dir.join("ExcelOutput").join(T::get_json_name()))
+    }
+
+    /// Errors mention the path of the file, so that users know which one is missing or broken
+    fn load_json_file<T: DeserializeOwned>(path: &Path) -> io::Result<T> {
+        let file = File::open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        })
+    }
+
     fn parse_json<T: DeserializeOwned>(str: &'static str) -> T {
         serde_json::de::from_str(str).unwrap()
     }
 
+    fn local_text_map(language: Language) -> Option<&'static str> {
+        // same issue with concat!() as above, so every language needs its own literal
+        macro_rules! text_map {
+            ($code:literal) => {
+                include_str!(concat!(env!("OUT_DIR"), "/TextMap", $code, ".json"))
+            };
+        }
+
+        Some(match language {
+            #[cfg(feature = "lang-chs")]
+            Language::Chs => text_map!("CHS"),
+            #[cfg(feature = "lang-cht")]
+            Language::Cht => text_map!("CHT"),
+            #[cfg(feature = "lang-de")]
+            Language::De => text_map!("DE"),
+            Language::En => text_map!("EN"),
+            #[cfg(feature = "lang-es")]
+            Language::Es => text_map!("ES"),
+            #[cfg(feature = "lang-fr")]
+            Language::Fr => text_map!("FR"),
+            #[cfg(feature = "lang-id")]
+            Language::Id => text_map!("ID"),
+            #[cfg(feature = "lang-jp")]
+            Language::Jp => text_map!("JP"),
+            #[cfg(feature = "lang-kr")]
+            Language::Kr => text_map!("KR"),
+            #[cfg(feature = "lang-pt")]
+            Language::Pt => text_map!("PT"),
+            #[cfg(feature = "lang-ru")]
+            Language::Ru => text_map!("RU"),
+            #[cfg(feature = "lang-th")]
+            Language::Th => text_map!("TH"),
+            #[cfg(feature = "lang-vi")]
+            Language::Vi => text_map!("VI"),
+            // not bundled in this build
+            #[allow(unreachable_patterns)]
+            _ => return None,
+        })
+    }
+
     fn load_local_keys() -> HashMap<u32, Vec<u8>> {
-        let keys: HashMap<u32, String> =
-            Self::parse_json(include_str!(concat!(env!("OUT_DIR"), "/keys.json")));
+        Self::decode_keys(Self::parse_json(include_str!(concat!(
+            env!("OUT_DIR"),
+            "/keys.json"
+        ))))
+        .unwrap()
+    }
+
+    /// Download decryption keys in the same format as the ones bundled at build time
+    pub fn load_online_keys(url: &str) -> Result<HashMap<u32, Vec<u8>>, Box<dyn Error>> {
+        // an unreachable host shouldn't hold up the capture for long, there are local keys
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(10))
+            .build();
+
+        let response = with_retry(
+            || agent.get(url).call(),
+            |e, attempt, delay| {
+                warn!(%e, attempt, "could not download decryption keys, retrying in {delay:.1?}");
+            },
+        )?
+        .into_string()?;
+        let keys: HashMap<u32, String> = serde_json::from_str(&response)?;
+
+        Ok(Self::decode_keys(keys)?)
+    }
+
+    fn decode_keys(keys: HashMap<u32, String>) -> Result<HashMap<u32, Vec<u8>>, DecodeError> {
         let mut keys_bytes = HashMap::new();
 
         for (k, v) in keys {
-            keys_bytes.insert(k, BASE64_STANDARD.decode(v).unwrap());
+            keys_bytes.insert(k, BASE64_STANDARD.decode(v)?);
         }
 
-        keys_bytes
+        Ok(keys_bytes)
+    }
+
+    /// The trailblazer has one avatar id per path and gender, starting at 8001
+    pub(crate) fn is_trailblazer(avatar_id: u32) -> bool {
+        avatar_id >= 8000
     }
 
     pub(crate) fn lookup_avatar_name(&self, avatar_id: u32) -> Option<String> {
@@ -92,11 +284,21 @@ impl Database {
             return None;
         }
 
-        if avatar_id >= 8000 {
-            Some("Trailblazer".to_owned())
-        } else {
-            let cfg = self.avatar_config.get(&avatar_id)?;
-            cfg.AvatarName.lookup(&self.text_map).map(|s| s.to_string())
+        let name = self
+            .avatar_config
+            .get(&avatar_id)
+            .and_then(|cfg| cfg.AvatarName.lookup(&self.text_map))
+            .map(|s| s.to_string());
+
+        if Self::is_trailblazer(avatar_id) {
+            // the trailblazer is named by the player, the text maps may only have a
+            // placeholder like {NICKNAME} for it
+            return Some(
+                name.filter(|name| !name.contains('{'))
+                    .unwrap_or_else(|| "Trailblazer".to_owned()),
+            );
         }
+
+        name
     }
 }
@@ -3,10 +3,11 @@
 //!
 //! [Fribbels HSR Optimizer]: https://github.com/fribbels/hsr-optimizer
 //! [kel-z's HSR-Scanner]: https://github.com/kel-z/HSR-Scanner
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crate::export::database::Database;
-use protobuf::Enum;
+use protobuf::{Enum, MessageDyn};
 use reliquary::network::gen::command_id;
 use reliquary::network::gen::proto::Avatar::Avatar as ProtoCharacter;
 use reliquary::network::gen::proto::AvatarSkillTree::AvatarSkillTree as ProtoSkillTree;
@@ -14,9 +15,12 @@ use reliquary::network::gen::proto::Equipment::Equipment as ProtoLightCone;
 use reliquary::network::gen::proto::GetAvatarDataScRsp::GetAvatarDataScRsp;
 use reliquary::network::gen::proto::GetBagScRsp::GetBagScRsp;
 use reliquary::network::gen::proto::GetMultiPathAvatarInfoScRsp::GetMultiPathAvatarInfoScRsp;
-use reliquary::network::gen::proto::MultiPathAvatarTypeInfo::MultiPathAvatarTypeInfo;
+use reliquary::network::gen::proto::GetPlayerBoardDataScRsp::GetPlayerBoardDataScRsp;
 use reliquary::network::gen::proto::MultiPathAvatarType::MultiPathAvatarType;
+use reliquary::network::gen::proto::MultiPathAvatarTypeInfo::MultiPathAvatarTypeInfo;
+use reliquary::network::gen::proto::PlayerBasicInfo::PlayerBasicInfo;
 use reliquary::network::gen::proto::PlayerGetTokenScRsp::PlayerGetTokenScRsp;
+use reliquary::network::gen::proto::PlayerLoginScRsp::PlayerLoginScRsp;
 use reliquary::network::gen::proto::Relic::Relic as ProtoRelic;
 use reliquary::network::gen::proto::RelicAffix::RelicAffix;
 use reliquary::network::GameCommand;
@@ -31,26 +35,415 @@ pub struct Export {
     pub build: &'static str,
     pub version: u32,
     pub metadata: Metadata,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currencies: Option<Currencies>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<AccountStats>,
     pub light_cones: Vec<LightCone>,
     pub relics: Vec<Relic>,
     pub characters: Vec<Character>,
+    /// `_uid`s of relics that are worth upgrading, most promising first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upgrade_priority: Option<Vec<String>>,
+    /// Sets equipped on each character, keyed by character id
+    pub equipped_sets: BTreeMap<String, Vec<EquippedSet>>,
+    #[serde(rename = "_debug", skip_serializing_if = "Option::is_none")]
+    pub debug: Option<DebugDump>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EquippedSet {
+    pub set_id: String,
+    pub name: String,
+    pub pieces: u32,
+    pub two_piece: bool,
+    /// Always `false` for planar ornament sets, which only have a 2 piece bonus
+    pub four_piece: bool,
+}
+
+/// Schema to write an [`Export`] in
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Format of the Fribbels HSR Optimizer
+    #[default]
+    Fribbels,
+    /// Older format of kel-z's HSR-Scanner, which refers to items by name instead of id
+    KelZScanner,
+}
+
+impl Export {
+    /// Only keep the characters with the given name, along with the relics and light cones
+    /// equipped on them and what is derived from those. Returns `false` and leaves the export
+    /// untouched if no character with that name exists
+    pub fn retain_character(&mut self, name: &str) -> bool {
+        let ids: Vec<String> = self
+            .characters
+            .iter()
+            .filter(|c| c.name.eq_ignore_ascii_case(name))
+            .map(|c| c.id.clone())
+            .collect();
+
+        if ids.is_empty() {
+            return false;
+        }
+
+        self.characters.retain(|c| ids.contains(&c.id));
+        self.relics.retain(|r| ids.contains(&r.location));
+        self.light_cones.retain(|l| ids.contains(&l.location));
+        self.equipped_sets.retain(|id, _| ids.contains(id));
+
+        if let Some(upgrade_priority) = &mut self.upgrade_priority {
+            upgrade_priority.retain(|uid| self.relics.iter().any(|r| &r._uid == uid));
+        }
+
+        true
+    }
+
+    /// Only the items that changed compared to a previous export. Relics and light cones
+    /// are matched by `_uid`, characters by `id`, and listed as added, updated or deleted
+    pub fn delta_from(&self, baseline: &serde_json::Value) -> serde_json::Value {
+        let current = serde_json::to_value(self).unwrap();
+
+        let diff =
+            |section: &str, key: &str| diff_items(baseline.get(section), current.get(section), key);
+
+        serde_json::json!({
+            "source": self.source,
+            "build": self.build,
+            "version": self.version,
+            "metadata": current["metadata"],
+            "light_cones": diff("light_cones", "_uid"),
+            "relics": diff("relics", "_uid"),
+            "characters": diff("characters", "id"),
+        })
+    }
+
+    /// Serialize the export into the schema of the given format
+    pub fn export_as(&self, format: ExportFormat) -> serde_json::Value {
+        match format {
+            ExportFormat::Fribbels => serde_json::to_value(self).unwrap(),
+            ExportFormat::KelZScanner => self.to_kel_z_scanner(),
+        }
+    }
+
+    /// The scanner identifies everything by name. Locations are character names,
+    /// relics are keyed by set name and light cones by their name
+    fn to_kel_z_scanner(&self) -> serde_json::Value {
+        let character_names: HashMap<&str, &str> = self
+            .characters
+            .iter()
+            .map(|c| (c.id.as_str(), c.name.as_str()))
+            .collect();
+
+        let location = |id: &str| character_names.get(id).copied().unwrap_or_default();
+
+        let light_cones: Vec<_> = self
+            .light_cones
+            .iter()
+            .map(|l| {
+                serde_json::json!({
+                    "key": l.name,
+                    "level": l.level,
+                    "ascension": l.ascension,
+                    "superimposition": l.superimposition,
+                    "location": location(&l.location),
+                    "lock": l.lock,
+                    "_id": l._uid,
+                })
+            })
+            .collect();
+
+        let relics: Vec<_> = self
+            .relics
+            .iter()
+            .map(|r| {
+                serde_json::json!({
+                    "set": r.name,
+                    "slot": r.slot,
+                    "rarity": r.rarity,
+                    "level": r.level,
+                    "mainstat": r.mainstat,
+                    "substats": r.substats,
+                    "location": location(&r.location),
+                    "lock": r.lock,
+                    "discard": r.discard,
+                    "_id": r._uid,
+                })
+            })
+            .collect();
+
+        let characters: Vec<_> = self
+            .characters
+            .iter()
+            .map(|c| {
+                serde_json::json!({
+                    "key": c.name,
+                    "level": c.level,
+                    "ascension": c.ascension,
+                    "eidolon": c.eidolon,
+                    "skills": c.skills,
+                    "traces": c.traces,
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "source": self.source,
+            "build": self.build,
+            // last scanner schema version before items were referred to by id
+            "version": 3,
+            "metadata": {
+                "uid": self.metadata.uid,
+                "trailblazer": self.metadata.trailblazer,
+            },
+            "light_cones": light_cones,
+            "relics": relics,
+            "characters": characters,
+        })
+    }
+}
+
+fn diff_items(
+    baseline: Option<&serde_json::Value>,
+    current: Option<&serde_json::Value>,
+    key: &str,
+) -> serde_json::Value {
+    let by_key = |items: Option<&serde_json::Value>| -> BTreeMap<String, serde_json::Value> {
+        items
+            .and_then(|items| items.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|item| Some((item.get(key)?.as_str()?.to_owned(), item.clone())))
+            .collect()
+    };
+
+    let baseline = by_key(baseline);
+    let current = by_key(current);
+
+    let mut added = Vec::new();
+    let mut updated = Vec::new();
+    for (id, item) in &current {
+        match baseline.get(id) {
+            None => added.push(item),
+            Some(previous) if previous != item => updated.push(item),
+            Some(_) => {}
+        }
+    }
+
+    let deleted: Vec<&String> = baseline
+        .keys()
+        .filter(|id| !current.contains_key(*id))
+        .collect();
+
+    serde_json::json!({
+        "added": added,
+        "updated": updated,
+        "deleted": deleted,
+    })
+}
+
+/// An inconsistency in an export, which downstream tools would likely choke on
+#[derive(Debug)]
+pub struct ValidationError {
+    /// `_uid` of the relic or light cone, or id of the character
+    pub item: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.item, self.message)
+    }
+}
+
+/// Checks the invariants downstream tools rely on, like every equipped item
+/// belonging to an exported character and all values being within the game's limits
+pub fn validate(export: &Export) -> Result<(), Vec<ValidationError>> {
+    let mut errors = Vec::new();
+    let mut error = |item: &str, message: String| {
+        errors.push(ValidationError {
+            item: item.to_owned(),
+            message,
+        })
+    };
+
+    let character_ids: HashSet<&str> = export.characters.iter().map(|c| c.id.as_str()).collect();
+    let is_known_location =
+        |location: &str| location.is_empty() || character_ids.contains(location);
+
+    for relic in &export.relics {
+        if !is_known_location(&relic.location) {
+            error(
+                &relic._uid,
+                format!("equipped on unknown character {}", relic.location),
+            );
+        }
+
+        if !MAIN_STATS.contains(&relic.mainstat.as_str()) {
+            error(&relic._uid, format!("unknown main stat {}", relic.mainstat));
+        }
+
+        if !(2..=5).contains(&relic.rarity) {
+            error(&relic._uid, format!("rarity {} out of range", relic.rarity));
+        }
+
+        // relics can be leveled up to 3 times their rarity
+        if relic.level > relic.rarity * 3 {
+            error(&relic._uid, format!("level {} out of range", relic.level));
+        }
+    }
+
+    for light_cone in &export.light_cones {
+        if !is_known_location(&light_cone.location) {
+            error(
+                &light_cone._uid,
+                format!("equipped on unknown character {}", light_cone.location),
+            );
+        }
+
+        if !(1..=5).contains(&light_cone.superimposition) {
+            error(
+                &light_cone._uid,
+                format!(
+                    "superimposition {} out of range",
+                    light_cone.superimposition
+                ),
+            );
+        }
+
+        if !(1..=80).contains(&light_cone.level) || light_cone.ascension > 6 {
+            error(
+                &light_cone._uid,
+                format!(
+                    "level {} or ascension {} out of range",
+                    light_cone.level, light_cone.ascension
+                ),
+            );
+        }
+    }
+
+    for character in &export.characters {
+        // multipath characters get their level from their base avatar, which might not have
+        // been captured. the exporter leaves their level at 0 then
+        let level_unknown = character.level == 0 && is_multipath_character(&character.id);
+
+        if !(level_unknown || (1..=80).contains(&character.level)) || character.ascension > 6 {
+            error(
+                &character.id,
+                format!(
+                    "level {} or ascension {} out of range",
+                    character.level, character.ascension
+                ),
+            );
+        }
+
+        if character.eidolon > 6 {
+            error(
+                &character.id,
+                format!("eidolon {} out of range", character.eidolon),
+            );
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn is_multipath_character(id: &str) -> bool {
+    id.parse::<i32>()
+        .ok()
+        .and_then(MultiPathAvatarType::from_i32)
+        .is_some()
+}
+
+/// Raw protos as received from the game, in protobuf's JSON mapping.
+/// Only meant to help with reproducing parsing bugs
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DebugDump {
+    pub bag: Vec<serde_json::Value>,
+    pub avatar: Vec<serde_json::Value>,
+    pub multipath: Vec<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Metadata {
     pub uid: Option<u32>,
     pub trailblazer: Option<&'static str>,
+    /// Unix timestamp of the next daily reset on the account's server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_reset: Option<i64>,
+    /// How the data was captured, to make exports reproducible in bug reports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture: Option<CaptureInfo>,
+    /// Ids of the characters lent to friends as support, empty if none are set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub support_characters: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaptureInfo {
+    /// Library used to capture packets
+    pub backend: String,
+    /// Whether packets were captured live or read from a capture file
+    pub live: bool,
+    /// Packet filter applied to the capture
+    pub filter: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Currencies {
+    pub credits: u32,
+    pub stellar_jade: u32,
+    pub oneiric_shards: u32,
+    pub trailblaze_power: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountStats {
+    pub trailblaze_level: u32,
+    pub equilibrium_level: u32,
+    pub trailblaze_exp: u32,
 }
 
 pub struct OptimizerExporter {
     database: Database,
     uid: Option<u32>,
     trailblazer: Option<&'static str>,
+    capture: Option<CaptureInfo>,
+    support_characters: Option<Vec<String>>,
+    /// unix timestamp used instead of the clock, to keep demo exports reproducible
+    pinned_time: Option<i64>,
+    currencies: Option<Currencies>,
+    stats: Option<AccountStats>,
+    inventory_received: bool,
     light_cones: Vec<LightCone>,
     relics: Vec<Relic>,
-    characters: Vec<Character>,
-    multipath_characters: Vec<Character>,
+    /// keyed by avatar id, so that character data received again later on
+    /// (e.g. after leveling up) replaces the previously recorded entries
+    characters: BTreeMap<u32, Character>,
+    /// keyed by avatar id, same as `characters`
+    multipath_characters: BTreeMap<u32, Character>,
     multipath_base_avatars: HashMap<u32, ProtoCharacter>,
+    upgrade_hints: bool,
+    upgrade_priority: bool,
+    light_cone_caps: bool,
+    substat_rolls: bool,
+    only_locked: bool,
+    exclude_discarded: bool,
+    /// relics below this rarity are left out of the export
+    relic_rarity_floor: Option<u32>,
+    debug: Option<DebugDump>,
+    /// time spent handling each kind of command, keyed by section
+    profile: Option<BTreeMap<&'static str, SectionTiming>>,
+}
+
+#[derive(Default)]
+struct SectionTiming {
+    count: u32,
+    total: Duration,
 }
 
 impl OptimizerExporter {
@@ -59,11 +452,26 @@ impl OptimizerExporter {
             database,
             uid: None,
             trailblazer: None,
+            capture: None,
+            support_characters: None,
+            pinned_time: None,
+            currencies: None,
+            stats: None,
+            inventory_received: false,
             light_cones: vec![],
             relics: vec![],
-            characters: vec![],
-            multipath_characters: vec![],
+            characters: BTreeMap::new(),
+            multipath_characters: BTreeMap::new(),
             multipath_base_avatars: HashMap::new(),
+            upgrade_hints: false,
+            upgrade_priority: false,
+            light_cone_caps: false,
+            substat_rolls: false,
+            only_locked: false,
+            exclude_discarded: false,
+            relic_rarity_floor: None,
+            debug: None,
+            profile: None,
         }
     }
 
@@ -71,24 +479,110 @@ impl OptimizerExporter {
         self.uid = Some(uid);
     }
 
+    /// Record how the data was captured in the export metadata
+    pub fn set_capture_info(&mut self, capture: CaptureInfo) {
+        self.capture = Some(capture);
+    }
+
+    pub fn set_currency_count(&mut self, basic_info: &PlayerBasicInfo) {
+        let currencies = Currencies {
+            credits: basic_info.scoin,
+            stellar_jade: basic_info.hcoin,
+            oneiric_shards: basic_info.mcoin,
+            trailblaze_power: basic_info.stamina,
+        };
+
+        info!(?currencies, "found currencies");
+        self.currencies = Some(currencies);
+
+        let stats = AccountStats {
+            trailblaze_level: basic_info.level,
+            equilibrium_level: basic_info.world_level,
+            trailblaze_exp: basic_info.exp,
+        };
+
+        info!(?stats, "found account stats");
+        self.stats = Some(stats);
+    }
+
+    pub fn set_support_characters(&mut self, board: &GetPlayerBoardDataScRsp) {
+        let support_characters: Vec<String> = board
+            .assist_avatar_id_list
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+
+        info!(?support_characters, "found support characters");
+        self.support_characters = Some(support_characters);
+    }
+
+    /// Attach an [`UpgradeHint`] to every exported relic
+    pub fn set_upgrade_hints(&mut self, enabled: bool) {
+        self.upgrade_hints = enabled;
+    }
+
+    /// Include a list of relics worth upgrading, ordered by how promising they are
+    pub fn set_upgrade_priority(&mut self, enabled: bool) {
+        self.upgrade_priority = enabled;
+    }
+
+    /// Attach the level cap of the current ascension to every exported light cone
+    pub fn set_light_cone_caps(&mut self, enabled: bool) {
+        self.light_cone_caps = enabled;
+    }
+
+    /// Attach how many rolls went into every relic substat, and how many max rolls they are worth
+    pub fn set_substat_rolls(&mut self, enabled: bool) {
+        self.substat_rolls = enabled;
+    }
+
+    /// Only export relics that are locked in-game
+    pub fn set_only_locked(&mut self, enabled: bool) {
+        self.only_locked = enabled;
+    }
+
+    /// Leave relics that are marked as trash in-game out of the export
+    pub fn set_exclude_discarded(&mut self, enabled: bool) {
+        self.exclude_discarded = enabled;
+    }
+
+    /// Leave relics with a rarity below `rarity` out of the export
+    pub fn set_relic_rarity_floor(&mut self, rarity: Option<u32>) {
+        self.relic_rarity_floor = rarity;
+    }
+
+    /// Attach the raw parsed protos of all captured sections under `_debug`
+    pub fn set_debug_export(&mut self, enabled: bool) {
+        self.debug = enabled.then(DebugDump::default);
+    }
+
+    /// Record how long each kind of command takes to handle and log a summary on export
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = enabled.then(BTreeMap::new);
+    }
+
     pub fn add_inventory(&mut self, bag: GetBagScRsp) {
-        let mut relics: Vec<Relic> = bag
+        self.inventory_received = true;
+
+        let relics: Vec<Relic> = bag
             .relic_list
             .iter()
-            .filter_map(|r| export_proto_relic(&self.database, r))
+            .filter_map(|r| export_proto_relic(&self.database, r, self.substat_rolls))
             .collect();
 
         info!(num = relics.len(), "found relics");
-        self.relics.append(&mut relics);
+        // the bag is a full snapshot of the inventory. it can be received again when capturing
+        // for longer, and then replaces everything, dropping items that were salvaged since
+        self.relics = relics;
 
-        let mut light_cones: Vec<LightCone> = bag
+        let light_cones: Vec<LightCone> = bag
             .equipment_list
             .iter()
             .filter_map(|equip| export_proto_light_cone(&self.database, equip))
             .collect();
 
         info!(num = light_cones.len(), "found light cones");
-        self.light_cones.append(&mut light_cones);
+        self.light_cones = light_cones;
     }
 
     pub fn add_characters(&mut self, characters: GetAvatarDataScRsp) {
@@ -97,13 +591,14 @@ impl OptimizerExporter {
                 MultiPathAvatarType::from_i32(a.base_avatar_id as i32).is_none()
             });
 
-        let mut characters: Vec<Character> = characters
+        let characters: Vec<Character> = characters
             .iter()
             .filter_map(|char| export_proto_character(&self.database, char))
             .collect();
 
         info!(num = characters.len(), "found characters");
-        self.characters.append(&mut characters);
+        self.characters
+            .extend(characters.into_iter().map(|c| (c.id.parse().unwrap(), c)));
 
         info!(
             num = multipath_characters.len(),
@@ -117,14 +612,17 @@ impl OptimizerExporter {
     }
 
     pub fn add_multipath_characters(&mut self, characters: GetMultiPathAvatarInfoScRsp) {
-        let mut characters: Vec<Character> = characters
+        let characters: Vec<Character> = characters
             .multi_path_avatar_type_info_list
             .iter()
             .filter_map(|char| export_proto_multipath_character(&self.database, char))
             .collect();
 
         // Try to find a trailblazer to determine the gender
-        if let Some(trailblazer) = characters.iter().find(|c| c.name == "Trailblazer") {
+        if let Some(trailblazer) = characters
+            .iter()
+            .find(|c| c.id.parse().is_ok_and(Database::is_trailblazer))
+        {
             self.trailblazer = Some(if trailblazer.id.parse::<u32>().unwrap() % 2 == 0 {
                 "Stelle"
             } else {
@@ -133,12 +631,44 @@ impl OptimizerExporter {
         }
 
         info!(num = characters.len(), "found multipath characters");
-        self.multipath_characters.append(&mut characters);
+        self.multipath_characters
+            .extend(characters.into_iter().map(|c| (c.id.parse().unwrap(), c)));
+    }
+
+    /// Fraction of the sections required by [`Exporter::is_finished`] that have been recorded
+    pub fn completeness(&self) -> f32 {
+        let sections = [
+            self.uid.is_some(),
+            self.trailblazer.is_some(),
+            self.has_inventory(),
+            !self.characters.is_empty(),
+            !self.multipath_characters.is_empty(),
+        ];
+
+        sections.iter().filter(|&&recorded| recorded).count() as f32 / sections.len() as f32
+    }
+
+    /// Brand-new accounts that are still early in the story might not have obtained any
+    /// relics or light cones yet
+    fn is_early_game(&self) -> bool {
+        self.stats
+            .as_ref()
+            .is_some_and(|stats| stats.equilibrium_level <= 1)
+    }
+
+    /// Whether the relics and light cones were recorded. For early-game accounts an
+    /// empty inventory is accepted, as long as the inventory was received at all
+    fn has_inventory(&self) -> bool {
+        if self.is_early_game() {
+            self.inventory_received
+        } else {
+            !self.relics.is_empty() && !self.light_cones.is_empty()
+        }
     }
 
     pub fn finalize_multipath_characters(&mut self) {
         // Fetch level & ascension
-        for character in self.multipath_characters.iter_mut() {
+        for character in self.multipath_characters.values_mut() {
             if let Some(config) = self
                 .database
                 .multipath_avatar_config
@@ -151,12 +681,183 @@ impl OptimizerExporter {
             }
         }
     }
+
+    /// Fills the exporter with a small, fixed set of data, so the output can be previewed
+    /// without capturing anything. The data is made up and does not belong to any account
+    pub fn add_demo_data(&mut self) {
+        info!("adding demo data");
+
+        self.uid = Some(800000000);
+        self.trailblazer = Some("Stelle");
+        self.support_characters = Some(vec!["1102".to_owned()]);
+        // 2025-01-01 00:00 utc
+        self.pinned_time = Some(1_735_689_600);
+
+        const SEELE: &str = "1102";
+
+        self.relics = vec![
+            demo_relic(1, MUSKETEER, "Head", "HP", SEELE),
+            demo_relic(2, MUSKETEER, "Hands", "ATK", SEELE),
+            demo_relic(3, MUSKETEER, "Body", "CRIT Rate", SEELE),
+            demo_relic(4, MUSKETEER, "Feet", "ATK", SEELE),
+            demo_relic(5, SEALING, "Planar Sphere", "Quantum DMG Boost", SEELE),
+            demo_relic(6, SEALING, "Link Rope", "ATK", SEELE),
+            Relic {
+                level: 0,
+                lock: false,
+                substats: vec![demo_substat("HP", 33.87), demo_substat("ATK_", 3.888)],
+                ..demo_relic(7, MUSKETEER, "Body", "DEF", "")
+            },
+        ];
+
+        self.light_cones = vec![
+            demo_light_cone(8, IN_THE_NIGHT, SEELE),
+            LightCone {
+                level: 1,
+                ascension: 0,
+                superimposition: 5,
+                lock: false,
+                ..demo_light_cone(9, GOOD_NIGHT_AND_SLEEP_WELL, "")
+            },
+        ];
+
+        self.characters
+            .insert(1102, demo_character(1102, "Seele", "Hunt"));
+        self.multipath_characters
+            .insert(8002, demo_character(8002, "Trailblazer", "Destruction"));
+    }
+}
+
+// made-up items for the demo data, also used as fixtures by the tests
+
+const MUSKETEER: (u32, &str) = (102, "Musketeer of Wild Wheat");
+const SEALING: (u32, &str) = (301, "Space Sealing Station");
+const IN_THE_NIGHT: (u32, &str) = (23001, "In the Night");
+const GOOD_NIGHT_AND_SLEEP_WELL: (u32, &str) = (21001, "Good Night and Sleep Well");
+
+fn demo_substat(key: &str, value: f32) -> Substat {
+    Substat {
+        key: key.to_owned(),
+        value,
+        rolls: None,
+        roll_value: None,
+    }
+}
+
+/// A locked 5 star relic at +15, with four substats that don't repeat its main stat
+fn demo_relic(
+    uid: u32,
+    (set_id, name): (u32, &str),
+    slot: &'static str,
+    mainstat: &str,
+    location: &str,
+) -> Relic {
+    // a substat can never be the same stat as the main stat, so crit rate and atk
+    // main stats get a set of substats without them
+    let substats = match mainstat {
+        "CRIT Rate" => vec![
+            demo_substat("CRIT DMG_", 17.496),
+            demo_substat("SPD", 4.6),
+            demo_substat("ATK_", 7.776),
+            demo_substat("Break Effect_", 5.832),
+        ],
+        "ATK" => vec![
+            demo_substat("CRIT Rate_", 8.1),
+            demo_substat("CRIT DMG_", 11.664),
+            demo_substat("HP_", 3.888),
+            demo_substat("Effect RES_", 7.776),
+        ],
+        _ => vec![
+            demo_substat("CRIT Rate_", 8.1),
+            demo_substat("CRIT DMG_", 11.664),
+            demo_substat("SPD", 4.6),
+            demo_substat("ATK_", 7.776),
+        ],
+    };
+
+    Relic {
+        set_id: set_id.to_string(),
+        name: name.to_owned(),
+        slot,
+        rarity: 5,
+        level: 15,
+        mainstat: mainstat.to_owned(),
+        substats,
+        location: location.to_owned(),
+        lock: true,
+        discard: false,
+        upgrade_hint: None,
+        conflict: None,
+        _uid: uid.to_string(),
+    }
+}
+
+/// A locked, fully leveled light cone at superimposition 1
+fn demo_light_cone(uid: u32, (id, name): (u32, &str), location: &str) -> LightCone {
+    LightCone {
+        id: id.to_string(),
+        name: name.to_owned(),
+        level: 80,
+        ascension: 6,
+        superimposition: 1,
+        location: location.to_owned(),
+        lock: true,
+        max_level: None,
+        _uid: uid.to_string(),
+    }
+}
+
+/// A fully leveled character at eidolon 0, with all traces unlocked
+fn demo_character(id: u32, name: &str, path: &str) -> Character {
+    Character {
+        id: id.to_string(),
+        name: name.to_owned(),
+        path: path.to_owned(),
+        level: 80,
+        ascension: 6,
+        eidolon: 0,
+        skills: Skills {
+            basic: 6,
+            skill: 10,
+            ult: 10,
+            talent: 10,
+        },
+        traces: Traces {
+            ability_1: true,
+            ability_2: true,
+            ability_3: true,
+            stat_1: true,
+            stat_2: true,
+            stat_3: true,
+            stat_4: true,
+            stat_5: true,
+            stat_6: true,
+            stat_7: true,
+            stat_8: true,
+            stat_9: true,
+            stat_10: true,
+        },
+    }
 }
 
 impl Exporter for OptimizerExporter {
     type Export = Export;
 
     fn read_command(&mut self, command: GameCommand) {
+        let profiled = self.profile.is_some().then(|| {
+            let section = match command.command_id {
+                command_id::PlayerGetTokenScRsp => "uid",
+                command_id::PlayerLoginScRsp => "login",
+                command_id::GetBagScRsp => "inventory",
+                command_id::GetAvatarDataScRsp => "characters",
+                command_id::GetMultiPathAvatarInfoScRsp => "multipath characters",
+                command_id::GetPlayerBoardDataScRsp => "support characters",
+                _ => "ignored",
+            };
+
+            (section, Instant::now())
+        });
+
         match command.command_id {
             command_id::PlayerGetTokenScRsp => {
                 debug!("detected uid");
@@ -168,11 +869,29 @@ impl Exporter for OptimizerExporter {
                     }
                 }
             }
+            command_id::PlayerLoginScRsp => {
+                debug!("detected login packet");
+                let cmd = command.parse_proto::<PlayerLoginScRsp>();
+                match cmd {
+                    Ok(cmd) => match cmd.basic_info.as_ref() {
+                        Some(basic_info) => self.set_currency_count(basic_info),
+                        None => warn!("login command did not contain basic info"),
+                    },
+                    Err(error) => {
+                        warn!(%error, "could not parse login command");
+                    }
+                }
+            }
             command_id::GetBagScRsp => {
                 debug!("detected inventory packet");
                 let cmd = command.parse_proto::<GetBagScRsp>();
                 match cmd {
-                    Ok(cmd) => self.add_inventory(cmd),
+                    Ok(cmd) => {
+                        if let Some(debug) = self.debug.as_mut() {
+                            debug.bag.push(proto_to_json(&cmd));
+                        }
+                        self.add_inventory(cmd)
+                    }
                     Err(error) => {
                         warn!(%error, "could not parse inventory data command");
                     }
@@ -182,7 +901,12 @@ impl Exporter for OptimizerExporter {
                 debug!("detected character packet");
                 let cmd = command.parse_proto::<GetAvatarDataScRsp>();
                 match cmd {
-                    Ok(cmd) => self.add_characters(cmd),
+                    Ok(cmd) => {
+                        if let Some(debug) = self.debug.as_mut() {
+                            debug.avatar.push(proto_to_json(&cmd));
+                        }
+                        self.add_characters(cmd)
+                    }
                     Err(error) => {
                         warn!(%error, "could not parse character data command");
                     }
@@ -192,12 +916,27 @@ impl Exporter for OptimizerExporter {
                 debug!("detected multipath packet (trailblazer/march 7th)");
                 let cmd = command.parse_proto::<GetMultiPathAvatarInfoScRsp>();
                 match cmd {
-                    Ok(cmd) => self.add_multipath_characters(cmd),
+                    Ok(cmd) => {
+                        if let Some(debug) = self.debug.as_mut() {
+                            debug.multipath.push(proto_to_json(&cmd));
+                        }
+                        self.add_multipath_characters(cmd)
+                    }
                     Err(error) => {
                         warn!(%error, "could not parse multipath data command");
                     }
                 }
             }
+            command_id::GetPlayerBoardDataScRsp => {
+                debug!("detected player board packet");
+                let cmd = command.parse_proto::<GetPlayerBoardDataScRsp>();
+                match cmd {
+                    Ok(cmd) => self.set_support_characters(&cmd),
+                    Err(error) => {
+                        warn!(%error, "could not parse player board command");
+                    }
+                }
+            }
             _ => {
                 trace!(
                     command_id = command.command_id,
@@ -206,21 +945,37 @@ impl Exporter for OptimizerExporter {
                 );
             }
         }
+
+        if let (Some((section, start)), Some(profile)) = (profiled, self.profile.as_mut()) {
+            let timing = profile.entry(section).or_default();
+            timing.count += 1;
+            timing.total += start.elapsed();
+        }
     }
 
     fn is_finished(&self) -> bool {
         self.trailblazer.is_some()
             && self.uid.is_some()
-            && !self.relics.is_empty()
+            && self.has_inventory()
             && !self.characters.is_empty()
             && !self.multipath_characters.is_empty()
-            && !self.light_cones.is_empty()
     }
 
     #[instrument(skip_all)]
     fn export(mut self) -> Self::Export {
         info!("exporting collected data");
 
+        if let Some(profile) = &self.profile {
+            for (section, timing) in profile {
+                info!(
+                    section,
+                    count = timing.count,
+                    total_ms = timing.total.as_secs_f64() * 1000.0,
+                    "command handling time"
+                );
+            }
+        }
+
         if self.trailblazer.is_none() {
             warn!("trailblazer gender was not recorded");
         }
@@ -229,12 +984,22 @@ impl Exporter for OptimizerExporter {
             warn!("uid was not recorded");
         }
 
-        if self.relics.is_empty() {
-            warn!("relics were not recorded");
-        }
+        if self.inventory_received && self.is_early_game() {
+            if self.relics.is_empty() {
+                info!("no relics in inventory yet");
+            }
+
+            if self.light_cones.is_empty() {
+                info!("no light cones in inventory yet");
+            }
+        } else {
+            if self.relics.is_empty() {
+                warn!("relics were not recorded");
+            }
 
-        if self.light_cones.is_empty() {
-            warn!("light cones were not recorded");
+            if self.light_cones.is_empty() {
+                warn!("light cones were not recorded");
+            }
         }
 
         if self.multipath_characters.is_empty() {
@@ -245,8 +1010,51 @@ impl Exporter for OptimizerExporter {
             warn!("characters were not recorded");
         }
 
+        info!("scan completeness: {:.0}%", self.completeness() * 100.0);
+
         self.finalize_multipath_characters();
 
+        if self.upgrade_hints {
+            for relic in self.relics.iter_mut() {
+                relic.upgrade_hint = Some(upgrade_hint(relic));
+            }
+        }
+
+        flag_equip_conflicts(&mut self.relics);
+
+        // sets are about what characters are wearing, so count them before filtering
+        let equipped_sets = equipped_sets(&self.relics);
+
+        let total = self.relics.len();
+        if self.only_locked {
+            self.relics.retain(|r| r.lock);
+        }
+        if self.exclude_discarded {
+            self.relics.retain(|r| !r.discard);
+        }
+        if let Some(min_rarity) = self.relic_rarity_floor {
+            self.relics.retain(|r| r.rarity >= min_rarity);
+        }
+        if self.relics.len() < total {
+            info!(num = total - self.relics.len(), "left out filtered relics");
+        }
+
+        if self.light_cone_caps {
+            for light_cone in self.light_cones.iter_mut() {
+                light_cone.max_level = Some(ascension_level_cap(light_cone.ascension));
+            }
+        }
+
+        let upgrade_priority = self
+            .upgrade_priority
+            .then(|| upgrade_priority(&self.relics));
+
+        let now = self.pinned_time.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as i64)
+        });
+
         Export {
             source: "reliquary_archiver",
             build: env!("CARGO_PKG_VERSION"),
@@ -254,18 +1062,61 @@ impl Exporter for OptimizerExporter {
             metadata: Metadata {
                 uid: self.uid,
                 trailblazer: self.trailblazer,
+                next_reset: self.uid.and_then(|uid| next_daily_reset(uid, now)),
+                capture: self.capture,
+                support_characters: self.support_characters,
             },
+            currencies: self.currencies,
+            stats: self.stats,
             light_cones: self.light_cones,
             relics: self.relics,
             characters: self
                 .characters
-                .into_iter()
-                .chain(self.multipath_characters)
+                .into_values()
+                .chain(self.multipath_characters.into_values())
                 .collect(),
+            upgrade_priority,
+            equipped_sets,
+            debug: self.debug,
         }
     }
 }
 
+fn proto_to_json(proto: &dyn MessageDyn) -> serde_json::Value {
+    let json = match protobuf_json_mapping::print_to_string(proto) {
+        Ok(json) => json,
+        Err(error) => {
+            warn!(%error, "could not convert proto to json");
+            return serde_json::Value::Null;
+        }
+    };
+
+    serde_json::from_str(&json).unwrap_or(serde_json::Value::Null)
+}
+
+/// Daily reset happens at 04:00 server time. The server, and with it the timezone,
+/// is determined by the first digit of the uid
+fn next_daily_reset(uid: u32, now: i64) -> Option<i64> {
+    const DAY: i64 = 24 * 60 * 60;
+    const RESET_HOUR: i64 = 4;
+
+    let utc_offset_hours: i64 = match uid.to_string().chars().next()? {
+        '6' => -5,                        // america
+        '7' => 1,                         // europe
+        '8' | '9' | '1' | '2' | '5' => 8, // asia, tw/hk/mo, china
+        _ => return None,
+    };
+
+    let server_now = now + utc_offset_hours * 60 * 60;
+
+    let mut server_reset = server_now - server_now.rem_euclid(DAY) + RESET_HOUR * 60 * 60;
+    if server_reset <= server_now {
+        server_reset += DAY;
+    }
+
+    Some(server_reset - utc_offset_hours * 60 * 60)
+}
+
 fn format_location(avatar_id: u32) -> String {
     if avatar_id == 0 {
         "".to_owned()
@@ -275,15 +1126,21 @@ fn format_location(avatar_id: u32) -> String {
 }
 
 #[tracing::instrument(name = "relic", skip_all, fields(id = proto.tid))]
-fn export_proto_relic(db: &Database, proto: &ProtoRelic) -> Option<Relic> {
+fn export_proto_relic(db: &Database, proto: &ProtoRelic, substat_rolls: bool) -> Option<Relic> {
     let relic_config = db.relic_config.get(&proto.tid)?;
 
     let set_id = relic_config.SetID;
     let set_config = db.relic_set_config.get(&set_id)?;
-    let main_affix_config = db
+    let Some(main_affix_config) = db
         .relic_main_affix_config
         .get(&relic_config.MainAffixGroup, &proto.main_affix_id)
-        .unwrap();
+    else {
+        warn!(
+            main_affix_id = proto.main_affix_id,
+            "unknown main affix, skipping relic"
+        );
+        return None;
+    };
 
     let id = proto.unique_id.to_string();
     let level = proto.level;
@@ -295,9 +1152,18 @@ fn export_proto_relic(db: &Database, proto: &ProtoRelic) -> Option<Relic> {
         .map(|s| s.to_string())
         .unwrap_or("".to_string());
 
-    let slot = slot_type_to_export(&relic_config.Type);
+    // new slots or stats get added every now and then. skip what we don't know
+    // instead of losing the whole capture
+    let Some(slot) = slot_type_to_export(&relic_config.Type) else {
+        warn!(slot = %relic_config.Type, "unknown relic slot, skipping relic");
+        return None;
+    };
     let rarity = relic_config.MaxLevel / 3;
-    let mainstat = main_stat_to_export(&main_affix_config.Property).to_string();
+    let Some(mainstat) = main_stat_to_export(&main_affix_config.Property) else {
+        warn!(stat = %main_affix_config.Property, "unknown main stat, skipping relic");
+        return None;
+    };
+    let mainstat = mainstat.to_string();
     let location = format_location(proto.equip_avatar_id);
 
     debug!(rarity, set_name, slot, slot, mainstat, location, "detected");
@@ -305,7 +1171,7 @@ fn export_proto_relic(db: &Database, proto: &ProtoRelic) -> Option<Relic> {
     let substats = proto
         .sub_affix_list
         .iter()
-        .filter_map(|substat| export_substat(db, rarity, substat))
+        .filter_map(|substat| export_substat(db, rarity, substat, substat_rolls))
         .collect();
 
     Some(Relic {
@@ -319,16 +1185,31 @@ fn export_proto_relic(db: &Database, proto: &ProtoRelic) -> Option<Relic> {
         location,
         lock,
         discard,
+        upgrade_hint: None,
+        conflict: None,
         _uid: id,
     })
 }
 
 #[tracing::instrument(name = "substat", skip_all)]
-fn export_substat(db: &Database, rarity: u32, substat: &RelicAffix) -> Option<Substat> {
+fn export_substat(
+    db: &Database,
+    rarity: u32,
+    substat: &RelicAffix,
+    with_rolls: bool,
+) -> Option<Substat> {
     let cfg = db.relic_sub_affix_config.get(&rarity, &substat.affix_id)?;
-    let key = sub_stat_to_export(&cfg.Property).to_string();
+    let Some(key) = sub_stat_to_export(&cfg.Property) else {
+        warn!(stat = %cfg.Property, "unknown sub stat, skipping sub stat");
+        return None;
+    };
+    let key = key.to_string();
 
-    let mut value = substat.cnt as f32 * *cfg.BaseValue + substat.step as f32 * *cfg.StepValue;
+    let (mut value, roll_value) =
+        substat_value(substat.cnt, substat.step, *cfg.BaseValue, *cfg.StepValue);
+
+    let rolls = with_rolls.then_some(substat.cnt);
+    let roll_value = with_rolls.then_some(roll_value);
 
     if key.ends_with('_') {
         value *= 100.0;
@@ -336,7 +1217,22 @@ fn export_substat(db: &Database, rarity: u32, substat: &RelicAffix) -> Option<Su
 
     trace!(key, value, "detected substat");
 
-    Some(Substat { key, value })
+    Some(Substat {
+        key,
+        value,
+        rolls,
+        roll_value,
+    })
+}
+
+/// Value of a substat rolled `cnt` times with `step` extra steps in total, and how many
+/// max rolls that is worth. Every roll adds the base value plus up to two steps, so a max
+/// roll is worth base + 2 * step. The initial value of a substat counts as a roll as well
+fn substat_value(cnt: u32, step: u32, base_value: f32, step_value: f32) -> (f32, f32) {
+    let value = cnt as f32 * base_value + step as f32 * step_value;
+    let roll_value = value / (base_value + 2.0 * step_value);
+
+    (value, roll_value)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -351,29 +1247,196 @@ pub struct Relic {
     pub location: String,
     pub lock: bool,
     pub discard: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upgrade_hint: Option<UpgradeHint>,
+    /// Set if another relic claims to be equipped in the same slot of the same character
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<bool>,
     pub _uid: String,
 }
 
+/// A character can only wear one relic per slot. If multiple relics claim the same
+/// character and slot, the recorded locations are inconsistent, so flag all of them
+fn flag_equip_conflicts(relics: &mut [Relic]) {
+    let mut equipped: HashMap<(&str, &str), usize> = HashMap::new();
+    for relic in relics.iter().filter(|r| !r.location.is_empty()) {
+        *equipped.entry((&relic.location, relic.slot)).or_default() += 1;
+    }
+
+    let conflicts: Vec<(String, &'static str)> = equipped
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|((location, slot), _)| (location.to_owned(), slot))
+        .collect();
+
+    for (location, slot) in &conflicts {
+        warn!(location, slot, "multiple relics equipped in the same slot");
+    }
+
+    for relic in relics.iter_mut() {
+        if conflicts
+            .iter()
+            .any(|(l, s)| *l == relic.location && *s == relic.slot)
+        {
+            relic.conflict = Some(true);
+        }
+    }
+}
+
+/// Counts the pieces of each set equipped on a character and which set bonuses they activate
+fn equipped_sets(relics: &[Relic]) -> BTreeMap<String, Vec<EquippedSet>> {
+    let mut pieces: BTreeMap<(&str, &str), (&str, u32, bool)> = BTreeMap::new();
+    for relic in relics.iter().filter(|r| !r.location.is_empty()) {
+        let is_planar = matches!(relic.slot, "Planar Sphere" | "Link Rope");
+        let entry =
+            pieces
+                .entry((&relic.location, &relic.set_id))
+                .or_insert((&relic.name, 0, is_planar));
+        entry.1 += 1;
+    }
+
+    let mut equipped_sets: BTreeMap<String, Vec<EquippedSet>> = BTreeMap::new();
+    for ((location, set_id), (name, pieces, is_planar)) in pieces {
+        equipped_sets
+            .entry(location.to_owned())
+            .or_default()
+            .push(EquippedSet {
+                set_id: set_id.to_owned(),
+                name: name.to_owned(),
+                pieces,
+                two_piece: pieces >= 2,
+                four_piece: !is_planar && pieces >= 4,
+            });
+    }
+
+    equipped_sets
+}
+
+/// Rough triage of whether a relic is worth investing into, derived purely from its
+/// rarity, slot and main stat. Not meant to replace an actual optimizer.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpgradeHint {
+    pub ideal_mainstat: bool,
+    /// Level the relic is worth upgrading to, equal to the current level if it isn't
+    /// worth investing any further
+    pub target_level: u32,
+}
+
+fn upgrade_hint(relic: &Relic) -> UpgradeHint {
+    let ideal_mainstat = is_ideal_main_stat(relic.slot, &relic.mainstat);
+
+    let target_level = if ideal_mainstat && relic.rarity == 5 {
+        15
+    } else {
+        relic.level
+    };
+
+    UpgradeHint {
+        ideal_mainstat,
+        target_level,
+    }
+}
+
+fn upgrade_priority(relics: &[Relic]) -> Vec<String> {
+    let mut candidates: Vec<(&Relic, usize)> = relics
+        .iter()
+        .filter(|r| r.level < upgrade_hint(r).target_level)
+        .map(|r| (r, upgrade_score(r)))
+        .collect();
+
+    // stable sort, so relics with the same score keep their inventory order
+    candidates.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    candidates
+        .into_iter()
+        .map(|(r, _)| r._uid.clone())
+        .collect()
+}
+
+/// Simple heuristic: every substat counts, substats that are commonly sought after count
+/// double. Relics that start out with four substats naturally score higher
+fn upgrade_score(relic: &Relic) -> usize {
+    relic
+        .substats
+        .iter()
+        .map(|s| {
+            if matches!(
+                s.key.as_str(),
+                "CRIT Rate_" | "CRIT DMG_" | "SPD" | "ATK_" | "Break Effect_" | "Effect Hit Rate_"
+            ) {
+                2
+            } else {
+                1
+            }
+        })
+        .sum()
+}
+
+/// Main stats that are commonly sought after per slot. Head and hands only ever
+/// have a single possible main stat, so they always count as ideal
+fn is_ideal_main_stat(slot: &str, mainstat: &str) -> bool {
+    match slot {
+        "Head" | "Hands" => true,
+        "Body" => matches!(
+            mainstat,
+            "CRIT Rate" | "CRIT DMG" | "Effect Hit Rate" | "Outgoing Healing Boost"
+        ),
+        "Feet" => matches!(mainstat, "SPD" | "ATK"),
+        "Planar Sphere" => mainstat.ends_with("DMG Boost"),
+        "Link Rope" => matches!(
+            mainstat,
+            "Energy Regeneration Rate" | "Break Effect" | "ATK"
+        ),
+        _ => false,
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Substat {
     key: String,
     value: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rolls: Option<u32>,
+    /// Value of the substat in multiples of a max roll
+    #[serde(skip_serializing_if = "Option::is_none")]
+    roll_value: Option<f32>,
 }
 
-fn slot_type_to_export(s: &str) -> &'static str {
-    match s {
+fn slot_type_to_export(s: &str) -> Option<&'static str> {
+    Some(match s {
         "HEAD" => "Head",
         "HAND" => "Hands",
         "BODY" => "Body",
         "FOOT" => "Feet",
         "NECK" => "Planar Sphere",
         "OBJECT" => "Link Rope",
-        _ => panic!("Unknown slot: {}", s),
-    }
+        _ => return None,
+    })
 }
 
-fn main_stat_to_export(s: &str) -> &'static str {
-    match s {
+/// All main stats as named by [`main_stat_to_export`]
+const MAIN_STATS: &[&str] = &[
+    "HP",
+    "ATK",
+    "DEF",
+    "CRIT Rate",
+    "CRIT DMG",
+    "Outgoing Healing Boost",
+    "SPD",
+    "Effect Hit Rate",
+    "Physical DMG Boost",
+    "Fire DMG Boost",
+    "Ice DMG Boost",
+    "Lightning DMG Boost",
+    "Wind DMG Boost",
+    "Quantum DMG Boost",
+    "Imaginary DMG Boost",
+    "Break Effect",
+    "Energy Regeneration Rate",
+];
+
+fn main_stat_to_export(s: &str) -> Option<&'static str> {
+    Some(match s {
         "HPDelta" => "HP",
         "AttackDelta" => "ATK",
         "HPAddedRatio" => "HP",
@@ -393,12 +1456,12 @@ fn main_stat_to_export(s: &str) -> &'static str {
         "ImaginaryAddedRatio" => "Imaginary DMG Boost",
         "BreakDamageAddedRatioBase" => "Break Effect",
         "SPRatioBase" => "Energy Regeneration Rate",
-        _ => panic!("Unknown main stat: {}", s),
-    }
+        _ => return None,
+    })
 }
 
-fn sub_stat_to_export(s: &str) -> &'static str {
-    match s {
+fn sub_stat_to_export(s: &str) -> Option<&'static str> {
+    Some(match s {
         "HPDelta" => "HP",
         "AttackDelta" => "ATK",
         "HPAddedRatio" => "HP_",
@@ -411,10 +1474,8 @@ fn sub_stat_to_export(s: &str) -> &'static str {
         "StatusProbabilityBase" => "Effect Hit Rate_",
         "StatusResistanceBase" => "Effect RES_",
         "BreakDamageAddedRatioBase" => "Break Effect_",
-        _ => {
-            panic!("Unknown sub stat: {}", s)
-        }
-    }
+        _ => return None,
+    })
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -426,9 +1487,17 @@ pub struct LightCone {
     pub superimposition: u32,
     pub location: String,
     pub lock: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_level: Option<u32>,
     pub _uid: String,
 }
 
+/// Every ascension raises the level cap by 10, starting at 20 for unascended
+/// light cones up to 80 at max ascension
+fn ascension_level_cap(ascension: u32) -> u32 {
+    20 + 10 * ascension
+}
+
 #[instrument(name = "light_cone", skip_all, fields(id = proto.tid))]
 fn export_proto_light_cone(db: &Database, proto: &ProtoLightCone) -> Option<LightCone> {
     let cfg = db.equipment_config.get(&proto.tid)?;
@@ -453,6 +1522,7 @@ fn export_proto_light_cone(db: &Database, proto: &ProtoLightCone) -> Option<Ligh
         superimposition,
         location,
         lock: proto.is_protected,
+        max_level: None,
         _uid: proto.unique_id.to_string(),
     })
 }
@@ -689,3 +1759,293 @@ pub struct Traces {
     pub stat_9: bool,
     pub stat_10: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::database::Language;
+
+    #[test]
+    fn unknown_relic_properties_are_skipped() {
+        assert_eq!(slot_type_to_export("TAIL"), None);
+        assert_eq!(main_stat_to_export("NewStatAddedRatio"), None);
+        assert_eq!(sub_stat_to_export("NewStatDelta"), None);
+    }
+
+    #[test]
+    fn known_relic_properties_are_mapped() {
+        assert_eq!(slot_type_to_export("OBJECT"), Some("Link Rope"));
+        assert_eq!(
+            main_stat_to_export("SPRatioBase"),
+            Some("Energy Regeneration Rate")
+        );
+        assert_eq!(sub_stat_to_export("CriticalDamageBase"), Some("CRIT DMG_"));
+    }
+
+    #[test]
+    fn daily_reset_is_at_4am_server_time() {
+        // 2025-01-01 00:00 utc
+        let now = 1_735_689_600;
+
+        // america, utc-5: still 2024-12-31 on the server
+        assert_eq!(next_daily_reset(600000000, now), Some(now + 9 * 60 * 60));
+        // europe, utc+1
+        assert_eq!(next_daily_reset(700000000, now), Some(now + 3 * 60 * 60));
+        // asia, utc+8: already past today's reset
+        assert_eq!(next_daily_reset(800000000, now), Some(now + 20 * 60 * 60));
+        assert_eq!(next_daily_reset(300000000, now), None);
+    }
+
+    #[test]
+    fn relic_with_unknown_main_affix_is_skipped() {
+        let db = Database::new(Language::En);
+
+        // 5 star head of Passerby of Wandering Cloud, with a flat hp main stat
+        let mut proto = ProtoRelic {
+            tid: 61011,
+            main_affix_id: 1,
+            ..Default::default()
+        };
+        assert!(export_proto_relic(&db, &proto, false).is_some());
+
+        proto.main_affix_id = 999;
+        assert!(export_proto_relic(&db, &proto, false).is_none());
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    // base and step values of 5 star relic substats
+    const CRIT_RATE: (f32, f32) = (0.02592, 0.00324);
+    const CRIT_DMG: (f32, f32) = (0.05184, 0.00648);
+    const SPD: (f32, f32) = (2.0, 0.3);
+
+    #[test]
+    fn substat_rolled_multiple_times() {
+        let (value, roll_value) = substat_value(3, 4, CRIT_RATE.0, CRIT_RATE.1);
+        assert_close(value, 0.09072);
+        assert_close(roll_value, 2.8);
+    }
+
+    #[test]
+    fn max_rolled_substat_is_worth_one_roll() {
+        let (value, roll_value) = substat_value(1, 2, CRIT_DMG.0, CRIT_DMG.1);
+        assert_close(value, 0.0648);
+        assert_close(roll_value, 1.0);
+    }
+
+    #[test]
+    fn min_rolled_substat_is_worth_less_than_its_rolls() {
+        let (value, roll_value) = substat_value(2, 0, SPD.0, SPD.1);
+        assert_close(value, 4.0);
+        assert_close(roll_value, 4.0 / 2.6);
+    }
+
+    fn relic(location: &str) -> Relic {
+        demo_relic(1, MUSKETEER, "Head", "HP", location)
+    }
+
+    fn light_cone(location: &str) -> LightCone {
+        demo_light_cone(2, IN_THE_NIGHT, location)
+    }
+
+    fn character(id: &str) -> Character {
+        demo_character(id.parse().unwrap(), "Seele", "Hunt")
+    }
+
+    fn export(
+        relics: Vec<Relic>,
+        light_cones: Vec<LightCone>,
+        characters: Vec<Character>,
+    ) -> Export {
+        Export {
+            source: "reliquary_archiver",
+            build: "test",
+            version: 4,
+            metadata: Metadata {
+                uid: None,
+                trailblazer: None,
+                next_reset: None,
+                capture: None,
+                support_characters: None,
+            },
+            currencies: None,
+            stats: None,
+            light_cones,
+            relics,
+            characters,
+            upgrade_priority: None,
+            equipped_sets: BTreeMap::new(),
+            debug: None,
+        }
+    }
+
+    #[test]
+    fn retained_character_keeps_only_its_own_items() {
+        let kept = demo_relic(10, MUSKETEER, "Head", "HP", "1102");
+        let dropped = demo_relic(11, MUSKETEER, "Head", "HP", "1005");
+
+        let mut export = export(
+            vec![kept, dropped],
+            vec![light_cone("1102"), light_cone("1005")],
+            vec![character("1102")],
+        );
+        export.upgrade_priority = Some(vec!["11".to_owned(), "10".to_owned()]);
+        export.equipped_sets = equipped_sets(&export.relics);
+
+        assert!(export.retain_character("seele"));
+        assert_eq!(export.relics.len(), 1);
+        assert_eq!(export.light_cones.len(), 1);
+        assert_eq!(export.upgrade_priority, Some(vec!["10".to_owned()]));
+        assert_eq!(export.equipped_sets.keys().collect::<Vec<_>>(), ["1102"]);
+
+        assert!(!export.retain_character("kafka"));
+    }
+
+    fn assert_invalid(export: Export, message: &str) {
+        let errors = validate(&export).unwrap_err();
+        assert!(
+            errors.iter().any(|e| e.message.contains(message)),
+            "expected an error containing {message:?}, got {errors:?}"
+        );
+    }
+
+    #[test]
+    fn consistent_export_is_valid() {
+        let export = export(
+            vec![relic("1102"), relic("")],
+            vec![light_cone("1102")],
+            vec![character("1102")],
+        );
+
+        assert!(validate(&export).is_ok());
+    }
+
+    #[test]
+    fn relic_on_unknown_character() {
+        assert_invalid(
+            export(vec![relic("1005")], vec![], vec![character("1102")]),
+            "equipped on unknown character 1005",
+        );
+    }
+
+    #[test]
+    fn relic_with_unknown_main_stat() {
+        let relic = Relic {
+            mainstat: "HP_".to_owned(),
+            ..relic("")
+        };
+
+        assert_invalid(export(vec![relic], vec![], vec![]), "unknown main stat HP_");
+    }
+
+    #[test]
+    fn relic_rarity_out_of_range() {
+        let relic = Relic {
+            rarity: 6,
+            ..relic("")
+        };
+
+        assert_invalid(export(vec![relic], vec![], vec![]), "rarity 6 out of range");
+    }
+
+    #[test]
+    fn relic_level_above_rarity_cap() {
+        let relic = Relic {
+            rarity: 3,
+            level: 12,
+            ..relic("")
+        };
+
+        assert_invalid(export(vec![relic], vec![], vec![]), "level 12 out of range");
+    }
+
+    #[test]
+    fn light_cone_on_unknown_character() {
+        assert_invalid(
+            export(vec![], vec![light_cone("1005")], vec![character("1102")]),
+            "equipped on unknown character 1005",
+        );
+    }
+
+    #[test]
+    fn light_cone_superimposition_out_of_range() {
+        let light_cone = LightCone {
+            superimposition: 0,
+            ..light_cone("")
+        };
+
+        assert_invalid(
+            export(vec![], vec![light_cone], vec![]),
+            "superimposition 0 out of range",
+        );
+    }
+
+    #[test]
+    fn light_cone_level_out_of_range() {
+        let light_cone = LightCone {
+            level: 90,
+            ..light_cone("")
+        };
+
+        assert_invalid(
+            export(vec![], vec![light_cone], vec![]),
+            "level 90 or ascension 6 out of range",
+        );
+    }
+
+    #[test]
+    fn character_level_out_of_range() {
+        let character = Character {
+            level: 0,
+            ..character("1102")
+        };
+
+        assert_invalid(
+            export(vec![], vec![], vec![character]),
+            "level 0 or ascension 6 out of range",
+        );
+    }
+
+    #[test]
+    fn character_ascension_out_of_range() {
+        let character = Character {
+            ascension: 7,
+            ..character("1102")
+        };
+
+        assert_invalid(
+            export(vec![], vec![], vec![character]),
+            "level 80 or ascension 7 out of range",
+        );
+    }
+
+    #[test]
+    fn character_eidolon_out_of_range() {
+        let character = Character {
+            eidolon: 7,
+            ..character("1102")
+        };
+
+        assert_invalid(
+            export(vec![], vec![], vec![character]),
+            "eidolon 7 out of range",
+        );
+    }
+
+    #[test]
+    fn multipath_character_without_base_avatar_is_valid() {
+        // the trailblazer's level stays at 0 if their base avatar wasn't captured
+        let trailblazer = Character {
+            level: 0,
+            ascension: 0,
+            ..character("8002")
+        };
+
+        assert!(validate(&export(vec![], vec![], vec![trailblazer])).is_ok());
+    }
+}
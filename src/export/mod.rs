@@ -2,6 +2,8 @@ use reliquary::network::GameCommand;
 
 pub mod database;
 pub mod fribbels;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
 pub trait Exporter {
     type Export;
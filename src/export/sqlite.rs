@@ -0,0 +1,232 @@
+//! Writes an [`Export`] into a SQLite database for tracking an account over time.
+//!
+//! Every item is upserted by its unique id, so running multiple captures into the same
+//! database keeps one row per item. `first_seen` and `last_seen` record the capture
+//! timestamps an item was first and most recently seen in, and every capture itself
+//! is recorded in `captures` with the item counts at that time.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection};
+use tracing::{info, instrument};
+
+use crate::export::fribbels::Export;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS captures (
+    captured_at INTEGER NOT NULL,
+    account     INTEGER NOT NULL,
+    build       TEXT    NOT NULL,
+    relics      INTEGER NOT NULL,
+    light_cones INTEGER NOT NULL,
+    characters  INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS relics (
+    account    INTEGER NOT NULL,
+    uid        TEXT    NOT NULL,
+    set_id     TEXT    NOT NULL,
+    name       TEXT    NOT NULL,
+    slot       TEXT    NOT NULL,
+    rarity     INTEGER NOT NULL,
+    level      INTEGER NOT NULL,
+    mainstat   TEXT    NOT NULL,
+    substats   TEXT    NOT NULL,
+    location   TEXT    NOT NULL,
+    lock       INTEGER NOT NULL,
+    discard    INTEGER NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen  INTEGER NOT NULL,
+    PRIMARY KEY (account, uid)
+);
+
+CREATE TABLE IF NOT EXISTS light_cones (
+    account         INTEGER NOT NULL,
+    uid             TEXT    NOT NULL,
+    id              TEXT    NOT NULL,
+    name            TEXT    NOT NULL,
+    level           INTEGER NOT NULL,
+    ascension       INTEGER NOT NULL,
+    superimposition INTEGER NOT NULL,
+    location        TEXT    NOT NULL,
+    lock            INTEGER NOT NULL,
+    first_seen      INTEGER NOT NULL,
+    last_seen       INTEGER NOT NULL,
+    PRIMARY KEY (account, uid)
+);
+
+CREATE TABLE IF NOT EXISTS characters (
+    account    INTEGER NOT NULL,
+    id         TEXT    NOT NULL,
+    name       TEXT    NOT NULL,
+    path       TEXT    NOT NULL,
+    level      INTEGER NOT NULL,
+    ascension  INTEGER NOT NULL,
+    eidolon    INTEGER NOT NULL,
+    skills     TEXT    NOT NULL,
+    traces     TEXT    NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen  INTEGER NOT NULL,
+    PRIMARY KEY (account, id)
+);
+";
+
+#[instrument(skip_all)]
+pub fn write_export(export: &Export, connection: &mut Connection) -> rusqlite::Result<()> {
+    connection.execute_batch(SCHEMA)?;
+
+    let captured_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    // exports without a recorded uid are grouped under account 0
+    let account = export.metadata.uid.unwrap_or(0);
+
+    let tx = connection.transaction()?;
+
+    tx.execute(
+        "INSERT INTO captures (captured_at, account, build, relics, light_cones, characters)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            captured_at,
+            account,
+            export.build,
+            export.relics.len(),
+            export.light_cones.len(),
+            export.characters.len(),
+        ],
+    )?;
+
+    for relic in &export.relics {
+        tx.execute(
+            "INSERT INTO relics (account, uid, set_id, name, slot, rarity, level, mainstat,
+                                 substats, location, lock, discard, first_seen, last_seen)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?13)
+             ON CONFLICT (account, uid) DO UPDATE SET
+                level = excluded.level,
+                substats = excluded.substats,
+                location = excluded.location,
+                lock = excluded.lock,
+                discard = excluded.discard,
+                last_seen = excluded.last_seen",
+            params![
+                account,
+                relic._uid,
+                relic.set_id,
+                relic.name,
+                relic.slot,
+                relic.rarity,
+                relic.level,
+                relic.mainstat,
+                serde_json::to_string(&relic.substats).unwrap(),
+                relic.location,
+                relic.lock,
+                relic.discard,
+                captured_at,
+            ],
+        )?;
+    }
+
+    for light_cone in &export.light_cones {
+        tx.execute(
+            "INSERT INTO light_cones (account, uid, id, name, level, ascension, superimposition,
+                                      location, lock, first_seen, last_seen)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?10)
+             ON CONFLICT (account, uid) DO UPDATE SET
+                level = excluded.level,
+                ascension = excluded.ascension,
+                superimposition = excluded.superimposition,
+                location = excluded.location,
+                lock = excluded.lock,
+                last_seen = excluded.last_seen",
+            params![
+                account,
+                light_cone._uid,
+                light_cone.id,
+                light_cone.name,
+                light_cone.level,
+                light_cone.ascension,
+                light_cone.superimposition,
+                light_cone.location,
+                light_cone.lock,
+                captured_at,
+            ],
+        )?;
+    }
+
+    for character in &export.characters {
+        tx.execute(
+            "INSERT INTO characters (account, id, name, path, level, ascension, eidolon,
+                                     skills, traces, first_seen, last_seen)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?10)
+             ON CONFLICT (account, id) DO UPDATE SET
+                level = excluded.level,
+                ascension = excluded.ascension,
+                eidolon = excluded.eidolon,
+                skills = excluded.skills,
+                traces = excluded.traces,
+                last_seen = excluded.last_seen",
+            params![
+                account,
+                character.id,
+                character.name,
+                character.path,
+                character.level,
+                character.ascension,
+                character.eidolon,
+                serde_json::to_string(&character.skills).unwrap(),
+                serde_json::to_string(&character.traces).unwrap(),
+                captured_at,
+            ],
+        )?;
+    }
+
+    tx.commit()?;
+
+    info!(
+        relics = export.relics.len(),
+        light_cones = export.light_cones.len(),
+        characters = export.characters.len(),
+        "wrote capture to database"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::database::{Database, Language};
+    use crate::export::fribbels::OptimizerExporter;
+    use crate::export::Exporter;
+
+    fn count_rows(connection: &Connection, table: &str) -> usize {
+        connection
+            .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn repeated_exports_keep_one_row_per_item() {
+        let mut exporter = OptimizerExporter::new(Database::new(Language::En));
+        exporter.add_demo_data();
+        let export = exporter.export();
+
+        let mut connection = Connection::open_in_memory().unwrap();
+        write_export(&export, &mut connection).unwrap();
+        write_export(&export, &mut connection).unwrap();
+
+        assert_eq!(count_rows(&connection, "captures"), 2);
+        assert_eq!(count_rows(&connection, "relics"), export.relics.len());
+        assert_eq!(
+            count_rows(&connection, "light_cones"),
+            export.light_cones.len()
+        );
+        assert_eq!(
+            count_rows(&connection, "characters"),
+            export.characters.len()
+        );
+    }
+}
@@ -1 +1,2 @@
 pub mod export;
+mod retry;
@@ -1,86 +1,440 @@
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
-use std::path::PathBuf;
-use std::sync::{mpsc, Mutex};
-use std::time::Duration;
+use std::io;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, Once};
+use std::time::{Duration, Instant};
 
 use clap::Parser;
-use pcap::{ConnectionStatus, Device, Error};
-use reliquary::network::gen::command_id::{PlayerLoginFinishScRsp, PlayerLoginScRsp};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use pcap::{ConnectionStatus, Device, Error, Linktype, PacketHeader, Savefile};
+use reliquary::network::gen::command_id::{
+    PlayerGetTokenScRsp, PlayerLoginFinishScRsp, PlayerLoginScRsp,
+};
 use reliquary::network::{ConnectionPacket, GamePacket, GameSniffer};
-use tracing::{debug, info, instrument, trace, warn};
+use serde::Serialize;
+use tracing::{debug, error, info, instrument, trace, warn};
 use tracing_subscriber::{prelude::*, EnvFilter, Layer, Registry};
 
 #[cfg(windows)] use {
     std::env,
     std::process::Command,
     self_update::cargo_crate_version,
-    tracing::error,
 };
 
-use reliquary_archiver::export::database::Database;
-use reliquary_archiver::export::fribbels::OptimizerExporter;
+use reliquary_archiver::export::database::{Database, Language};
+use reliquary_archiver::export::fribbels::{self, CaptureInfo, ExportFormat, OptimizerExporter};
+#[cfg(feature = "sqlite")]
+use reliquary_archiver::export::sqlite;
 use reliquary_archiver::export::Exporter;
 
-const PACKET_FILTER: &str = "udp portrange 23301-23302";
+/// Inclusive range of udp ports, written as `start-end`
+#[derive(Clone, Copy, Debug)]
+struct PortRange(u16, u16);
+
+impl PortRange {
+    fn packet_filter(self) -> String {
+        let PortRange(start, end) = self;
+        format!("udp portrange {start}-{end}")
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected a range like 23301-23302, got {s}"))?;
+
+        let parse_port = |port: &str| {
+            port.trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|&port| port != 0)
+                .ok_or_else(|| format!("{port} is not a valid port"))
+        };
+
+        let (start, end) = (parse_port(start)?, parse_port(end)?);
+        if start > end {
+            return Err(format!("start of the range {start} is after its end {end}"));
+        }
+
+        Ok(PortRange(start, end))
+    }
+}
+
+/// Range of udp ports the game servers communicate on
+const DEFAULT_PORT_RANGE: PortRange = PortRange(23301, 23302);
+
+/// Captured frames still contain the ethernet (14 bytes), ipv4 (20 bytes) and udp (8 bytes)
+/// headers. Frames too short to carry the 20 byte connection handshake on top of those (the
+/// smallest packet the game sends) can't be game packets, even though ethernet pads every
+/// frame to 60 bytes. They are dropped before reaching the sniffer, since junk data can
+/// throw off its kcp state
+const MIN_PACKET_LEN: usize = 14 + 20 + 8 + 20;
+
+fn long_enough(frame: &[u8]) -> bool {
+    frame.len() >= MIN_PACKET_LEN
+}
+
+/// How long game packets may keep coming in without the session token being captured,
+/// before warning that the capture was started too late
+const LOGIN_WARNING_AFTER: Duration = Duration::from_secs(30);
+
+/// How often idle captures check whether they should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// On linux the clipboard contents are gone once the program exits. When exiting right after
+/// the capture, it keeps serving the output for this long, so that it can still be pasted
+#[cfg(target_os = "linux")]
+const CLIPBOARD_WAIT: Duration = Duration::from_secs(60);
 
 #[derive(Parser, Debug)]
 struct Args {
+    /// Print version and build information, then exit
+    #[arg(short = 'V', long)]
+    version: bool,
+    /// Print the network devices available for capturing, then exit
+    #[arg(long)]
+    list_devices: bool,
     #[arg(default_value = "archive_output.json")]
     /// Path to output .json file to
     output: PathBuf,
+    /// Only export the character with this name, along with their equipped relics and light cone
+    #[arg(long)]
+    character: Option<String>,
+    /// Schema to write the output in
+    #[arg(long, value_enum, default_value_t)]
+    format: ExportFormat,
+    /// Leave relics below this rarity out of the export, e.g. 4 to only keep 4 and 5 star relics
+    #[arg(long, value_parser = clap::value_parser!(u32).range(2..=5))]
+    min_rarity: Option<u32>,
+    /// Only export relics that are locked in-game
+    #[arg(long)]
+    only_locked: bool,
+    /// Leave relics that are marked as trash in-game out of the export
+    #[arg(long)]
+    exclude_discarded: bool,
+    /// Only write what changed compared to this previous export, as lists of added,
+    /// updated and deleted items
+    #[arg(long)]
+    delta: Option<PathBuf>,
+    /// Write the output gzip-compressed, appending .gz to the output path if necessary
+    #[arg(long)]
+    gzip: bool,
+    /// Copy the export to the clipboard instead of writing it to the output file
+    #[arg(long)]
+    capture_to_clipboard: bool,
+    /// Open the output file with the default program for its file type after writing it
+    #[arg(long)]
+    open_output: bool,
+    /// Additionally write the export into a SQLite database at this path, keeping
+    /// track of items over multiple captures. Can't be combined with options that leave
+    /// items out of the export, since the database tracks the whole account
+    #[cfg(feature = "sqlite")]
+    #[arg(
+        long,
+        conflicts_with_all = ["character", "min_rarity", "only_locked", "exclude_discarded"]
+    )]
+    sqlite: Option<PathBuf>,
+    /// Capture on this range of udp ports instead of the game's usual 23301-23302
+    #[arg(long)]
+    ports: Option<PortRange>,
+    /// Export made-up demo data instead of capturing, useful to preview the output
+    #[arg(long)]
+    demo: bool,
+    /// Language of the names of characters, light cones and relic sets in the export
+    #[arg(long, value_enum, default_value_t)]
+    lang: Language,
+    /// Load the game data from this directory instead of using the data bundled at build time.
+    /// Expects the layout of the resource repository, plus the decryption keys as Keys.json
+    #[arg(long)]
+    database_dir: Option<PathBuf>,
+    /// Only capture on this network device, given by its index or name as printed by --list-devices
+    #[arg(long)]
+    device: Option<String>,
+    /// Download the decryption keys instead of using the local ones, from the url the bundled
+    /// keys were taken from or the one given as --keys-url=<url>. Falls back to the local keys
+    /// if the download fails
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = env!("KEYS_URL")
+    )]
+    keys_url: Option<String>,
     /// Read packets from .pcap file instead of capturing live packets
     #[arg(long)]
     pcap: Option<PathBuf>,
+    /// Save the packets of a live capture to this .pcap file, to be replayed later with --pcap
+    #[arg(long)]
+    save_capture: Option<PathBuf>,
     /// How long to wait in seconds until timeout is triggered for live captures
     #[arg(long, default_value_t = 120)]
     timeout: u64,
+    /// How many packets may be received after the connection handshake without being able
+    /// to decode a single command, before warning about outdated decryption keys
+    #[arg(long, default_value_t = 200)]
+    decode_warning_after: u64,
     /// How verbose the output should be, can be set up to 3 times. Has no effect if RUST_LOG is set
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
     /// Path to output log to
     #[arg(short, long)]
     log_path: Option<PathBuf>,
+    /// Write a json crash report if the archiver crashes, to crash_report.json or the path
+    /// given as --crash-report=<path>
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "crash_report.json"
+    )]
+    crash_report: Option<PathBuf>,
+    /// Don't write the output and exit with an error if the export is inconsistent
+    #[arg(long)]
+    strict: bool,
+    /// Keep listening after the login data was received, to also pick up data the game sends
+    /// later on (e.g. when opening the inventory). Stops on timeout or ctrl-c
+    #[arg(long)]
+    keep_alive: bool,
+    /// Exit right after writing the output instead of waiting for enter to be pressed
+    #[arg(long)]
+    exit_after_capture: bool,
     /// Don't check for updates, only applicable on Windows
     #[arg(long)]
     no_update: bool,
     /// Github Auth token to use when checking for updates, only applicable on Windows
     #[arg(long)]
     auth_token: Option<String>,
+    /// Update without asking for confirmation, only applicable on Windows
+    #[arg(long)]
+    always_update: bool,
+    /// Also update to prerelease builds, only applicable on Windows
+    #[arg(long)]
+    prerelease: bool,
+    /// Include hints on whether a relic is worth upgrading in the export
+    #[arg(long)]
+    upgrade_hints: bool,
+    /// Include a list of relics worth upgrading next, ordered by a simple heuristic
+    #[arg(long)]
+    upgrade_priority: bool,
+    /// Include the level cap of each light cone's current ascension in the export
+    #[arg(long)]
+    light_cone_caps: bool,
+    /// Include how many rolls went into each relic substat, and how many max rolls they are worth
+    #[arg(long)]
+    substat_rolls: bool,
+    /// Attach the raw received protos under a `_debug` key for debugging parsing issues
+    #[arg(long)]
+    debug_export: bool,
+    /// Log how long handling each kind of packet took once capturing is done
+    #[arg(long)]
+    profile: bool,
+}
+
+impl Args {
+    fn packet_filter(&self) -> String {
+        self.ports.unwrap_or(DEFAULT_PORT_RANGE).packet_filter()
+    }
 }
 
 fn main() {
     color_eyre::install().unwrap();
     let args = Args::parse();
 
-    tracing_init(&args);
+    if args.version {
+        print_build_info();
+        return;
+    }
+
+    if args.list_devices {
+        list_devices();
+        return;
+    }
+
+    let recent_logs = tracing_init(&args);
+
+    if let Some(path) = &args.crash_report {
+        install_crash_reporter(path.clone(), recent_logs);
+    }
+
+    install_recovered_panic_hook();
 
     debug!(?args);
 
     // Only self update on Windows, since that's the only platform we ship releases for
     #[cfg(windows)] {
         if !args.no_update && !env::var("NO_SELF_UPDATE").map_or(false, |v| v == "1") {
-            if let Err(e) = update(args.auth_token.as_deref()) {
+            if let Err(e) = update(
+                args.auth_token.as_deref(),
+                args.always_update,
+                args.prerelease,
+            ) {
                 error!("Failed to update: {}", e);
             }
         }
     }
 
-    let database = Database::new();
-    let sniffer = GameSniffer::new().set_initial_keys(database.keys.clone());
-    let exporter = OptimizerExporter::new(database);
+    #[cfg(windows)]
+    check_architecture();
 
-    let export = match args.pcap {
-        Some(_) => file_capture(&args, exporter, sniffer),
-        None => live_capture(&args, exporter, sniffer),
+    let mut database = match &args.database_dir {
+        Some(dir) => match Database::new_from_directory(dir, args.lang) {
+            Ok(database) => database,
+            Err(e) => {
+                error!(%e, "could not load database");
+                return;
+            }
+        },
+        None => Database::new(args.lang),
     };
 
-    if let Some(export) = export {
-        let file = File::create(&args.output).unwrap();
-        serde_json::to_writer_pretty(&file, &export).unwrap();
-        info!(
-            "wrote output to {}",
-            &args.output.canonicalize().unwrap().display()
-        );
+    let keys_downloaded = match &args.keys_url {
+        Some(url) => match Database::load_online_keys(url) {
+            Ok(keys) => {
+                let source = if url == env!("KEYS_URL") {
+                    "the default keys url"
+                } else {
+                    "--keys-url"
+                };
+                info!(url, "using decryption keys downloaded from {source}");
+                database.keys = keys;
+                true
+            }
+            Err(e) => {
+                warn!(%e, url, "could not download decryption keys");
+                false
+            }
+        },
+        None => false,
+    };
+
+    if !keys_downloaded {
+        match &args.database_dir {
+            Some(dir) => info!("using the decryption keys in {}", dir.display()),
+            None => info!(
+                url = env!("KEYS_URL"),
+                "using the decryption keys embedded at build time"
+            ),
+        }
+    }
+
+    let keys = database.keys.clone();
+    let mut exporter = OptimizerExporter::new(database);
+    exporter.set_upgrade_hints(args.upgrade_hints);
+    exporter.set_upgrade_priority(args.upgrade_priority);
+    exporter.set_light_cone_caps(args.light_cone_caps);
+    exporter.set_substat_rolls(args.substat_rolls);
+    exporter.set_relic_rarity_floor(args.min_rarity);
+    exporter.set_only_locked(args.only_locked);
+    exporter.set_exclude_discarded(args.exclude_discarded);
+    exporter.set_debug_export(args.debug_export);
+    exporter.set_profiling(args.profile);
+
+    if !args.demo {
+        exporter.set_capture_info(CaptureInfo {
+            backend: "pcap".to_owned(),
+            live: args.pcap.is_none(),
+            filter: args.packet_filter(),
+        });
+    }
+
+    let export = if args.demo {
+        exporter.add_demo_data();
+        Some(exporter.export())
+    } else {
+        match args.pcap {
+            Some(_) => file_capture(&args, exporter, keys),
+            None => live_capture(&args, exporter, keys),
+        }
+    };
+
+    // on some platforms the clipboard contents are only available for as long as
+    // the clipboard is alive, so keep it around until the program exits
+    let mut _clipboard = None;
+
+    if let Some(mut export) = export {
+        if let Some(character) = &args.character {
+            if export.retain_character(character) {
+                info!(character, "only exporting a single character");
+            } else {
+                warn!(
+                    character,
+                    "character not found, exporting everything instead"
+                );
+            }
+        }
+
+        if let Err(errors) = fribbels::validate(&export) {
+            for e in &errors {
+                warn!(%e, "inconsistent export");
+            }
+
+            if args.strict {
+                error!(
+                    num = errors.len(),
+                    "export is inconsistent, not writing output"
+                );
+                std::process::exit(1);
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite_path) = &args.sqlite {
+            let written = rusqlite::Connection::open(sqlite_path)
+                .and_then(|mut connection| sqlite::write_export(&export, &mut connection));
+            match written {
+                Ok(()) => info!("wrote output to database {}", sqlite_path.display()),
+                Err(e) => error!(%e, "could not write to database"),
+            }
+        }
+
+        let output = match &args.delta {
+            Some(baseline_path) => match read_baseline(baseline_path) {
+                Ok(baseline) => {
+                    if args.format != ExportFormat::Fribbels {
+                        warn!("deltas are always written in the fribbels format");
+                    }
+                    export.delta_from(&baseline)
+                }
+                Err(e) => {
+                    error!(%e, "could not read {}, writing the full export", baseline_path.display());
+                    export.export_as(args.format)
+                }
+            },
+            None => export.export_as(args.format),
+        };
+
+        if args.capture_to_clipboard {
+            // the process exits right after, don't take the clipboard contents with it
+            match copy_to_clipboard(&output, args.exit_after_capture) {
+                Ok(clipboard) => {
+                    info!("copied output to clipboard");
+                    _clipboard = Some(clipboard);
+                }
+                Err(e) => error!(%e, "could not copy output to clipboard"),
+            }
+        } else if let Some(path) = write_output(output_path(&args), &output, args.gzip) {
+            let path = path.canonicalize().unwrap();
+            info!("wrote output to {}", path.display());
+
+            if args.open_output {
+                if let Err(e) = open::that(&path) {
+                    error!(%e, "could not open output");
+                }
+            }
+        } else {
+            warn!("skipped writing output");
+        }
     } else {
         warn!("skipped writing output");
     }
@@ -89,13 +443,140 @@ fn main() {
         info!("wrote logs to {}", log_path.display());
     }
 
-    info!("press enter to close");
-    std::io::stdin().read_line(&mut String::new()).unwrap();
+    if !args.exit_after_capture {
+        info!("press enter to close");
+        std::io::stdin().read_line(&mut String::new()).unwrap();
+    }
+}
+
+fn output_path(args: &Args) -> PathBuf {
+    let is_gz = args.output.extension().is_some_and(|ext| ext == "gz");
+
+    if args.gzip && !is_gz {
+        let mut path = args.output.clone().into_os_string();
+        path.push(".gz");
+        path.into()
+    } else {
+        args.output.clone()
+    }
+}
+
+/// Writes the export as json. If writing fails (e.g. the directory is read-only),
+/// the user is asked for another path instead of losing the export.
+/// Returns the path the export was written to, or `None` if the user gave up
+fn write_output(mut path: PathBuf, export: &impl Serialize, gzip: bool) -> Option<PathBuf> {
+    loop {
+        let result = File::create(&path).and_then(|file| {
+            if gzip {
+                let mut encoder = GzEncoder::new(file, Compression::default());
+                serde_json::to_writer_pretty(&mut encoder, export)?;
+                encoder.finish().map(|_| ())
+            } else {
+                serde_json::to_writer_pretty(&file, export).map_err(io::Error::from)
+            }
+        });
+
+        match result {
+            Ok(()) => return Some(path),
+            Err(e) => {
+                error!(%e, "could not write output to {}", path.display());
+                info!("enter another path to write the output to, or leave empty to skip");
+
+                let mut input = String::new();
+                if io::stdin().read_line(&mut input).is_err() || input.trim().is_empty() {
+                    return None;
+                }
+
+                path = PathBuf::from(input.trim());
+            }
+        }
+    }
+}
+
+/// Reads a previous export, which may be gzip-compressed
+fn read_baseline(path: &Path) -> io::Result<serde_json::Value> {
+    let file = io::BufReader::new(File::open(path)?);
+
+    let baseline = if path.extension().is_some_and(|ext| ext == "gz") {
+        serde_json::from_reader(GzDecoder::new(file))?
+    } else {
+        serde_json::from_reader(file)?
+    };
+
+    Ok(baseline)
+}
+
+/// On linux the clipboard contents are served by this process and disappear once it exits.
+/// With `wait`, this blocks until another application takes over the clipboard instead
+fn copy_to_clipboard(
+    export: &impl Serialize,
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))] wait: bool,
+) -> Result<arboard::Clipboard, arboard::Error> {
+    let mut clipboard = arboard::Clipboard::new()?;
+    let text = serde_json::to_string(export).unwrap();
+
+    #[cfg(target_os = "linux")]
+    if wait {
+        use arboard::SetExtLinux;
+
+        info!(
+            "keeping the output on the clipboard for {} seconds or until something else is copied",
+            CLIPBOARD_WAIT.as_secs()
+        );
+        clipboard
+            .set()
+            .wait_until(Instant::now() + CLIPBOARD_WAIT)
+            .text(text)?;
+        return Ok(clipboard);
+    }
+
+    clipboard.set_text(text)?;
+    Ok(clipboard)
+}
+
+fn print_build_info() {
+    println!("reliquary-archiver {}", env!("CARGO_PKG_VERSION"));
+    println!("target: {}", env!("BUILD_TARGET"));
+    println!("capture backend: pcap");
+    println!("resource data: {}", env!("RESOURCE_URL"));
+    println!("keys: {}", env!("KEYS_URL"));
+
+    let languages: Vec<&str> = Language::compiled().iter().map(|l| l.code()).collect();
+    println!("languages: {}", languages.join(", "));
+}
+
+fn list_devices() {
+    println!("capture backend: pcap");
+
+    match Device::list() {
+        Ok(devices) if devices.is_empty() => println!("no network devices found"),
+        Ok(devices) => {
+            for (i, device) in devices.iter().enumerate() {
+                println!("{i}: {device:#?}");
+            }
+        }
+        Err(e) => println!("could not list network devices: {e}"),
+    }
 }
 
 #[cfg(windows)]
-fn update(auth_token: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
-    info!("checking for updates");
+fn update(
+    auth_token: Option<&str>,
+    always_update: bool,
+    prerelease: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!(
+        channel = if prerelease { "prerelease" } else { "stable" },
+        "checking for updates"
+    );
+
+    // releases name their assets after the architecture, e.g. reliquary-archiver_x64.exe.
+    // stick to the architecture of the running build, x64 builds also run emulated on arm
+    let target = if cfg!(target_arch = "aarch64") {
+        "arm64"
+    } else {
+        "x64"
+    };
 
     let mut update_builder = self_update::backends::github::Update::configure();
 
@@ -103,16 +584,77 @@ fn update(auth_token: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
         .repo_owner("IceDynamix")
         .repo_name("reliquary-archiver")
         .bin_name("reliquary-archiver")
-        .target("x64")
+        .target(target)
         .show_download_progress(true)
         .show_output(false)
+        .no_confirm(always_update)
         .current_version(cargo_crate_version!());
 
     if let Some(token) = auth_token {
         update_builder.auth_token(token);
     }
 
-    let status = update_builder.build()?.update()?;
+    // the latest release endpoint never returns prereleases, but the release list
+    // does, newest first
+    let newest_release = if prerelease {
+        let mut release_list = self_update::backends::github::ReleaseList::configure();
+        release_list
+            .repo_owner("IceDynamix")
+            .repo_name("reliquary-archiver")
+            .with_target(target);
+
+        if let Some(token) = auth_token {
+            release_list.auth_token(token);
+        }
+
+        let releases = release_list.build()?.fetch()?;
+        let newest = releases
+            .into_iter()
+            .next()
+            .ok_or_else(|| format!("no release has a {target} build"))?;
+        update_builder.target_version_tag(&format!("v{}", newest.version));
+
+        Some(newest)
+    } else {
+        None
+    };
+
+    let updater = update_builder.build()?;
+
+    // show what we would be updating to before the binary gets replaced,
+    // so users can opt out of a release they don't want
+    let release = match newest_release {
+        Some(release) => release,
+        None => updater.get_latest_release()?,
+    };
+
+    // rather not update at all than replace the binary with one that can't run
+    if release.asset_for(target, None).is_none() {
+        warn!(
+            version = release.version,
+            "release has no {target} build, skipping update"
+        );
+        return Ok(());
+    }
+
+    if self_update::version::bump_is_greater(cargo_crate_version!(), &release.version)? {
+        info!(
+            current = cargo_crate_version!(),
+            target = release.version,
+            "new version available"
+        );
+
+        if let Some(notes) = release.body.as_deref().filter(|b| !b.trim().is_empty()) {
+            info!("release notes for {}:\n{}", release.name, notes.trim());
+        }
+    } else if updater.target_version().is_some() {
+        // with a fixed target version, self_update doesn't compare versions itself
+        // and would reinstall (or downgrade to) the same release every time
+        info!("already up-to-date");
+        return Ok(());
+    }
+
+    let status = updater.update()?;
 
     if status.updated() {
         info!("updated to {}", status.version());
@@ -132,7 +674,84 @@ fn update(auth_token: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn tracing_init(args: &Args) {
+/// Warns about running the x64 build on an ARM machine, where loading the x64 npcap
+/// dlls fails with a rather cryptic "%1 is not a valid Win32 application" error
+#[cfg(windows)]
+fn check_architecture() {
+    // emulated x64 processes see PROCESSOR_ARCHITECTURE=AMD64, but the
+    // identifier still describes the actual cpu
+    let host_is_arm = env::var("PROCESSOR_IDENTIFIER").is_ok_and(|id| id.starts_with("ARM"))
+        || env::var("PROCESSOR_ARCHITEW6432").is_ok_and(|arch| arch == "ARM64");
+
+    if host_is_arm && cfg!(target_arch = "x86_64") {
+        warn!("detected an ARM cpu, but this is the x64 build of the archiver");
+        warn!("if capturing fails with \"%1 is not a valid Win32 application\",");
+        warn!("build the archiver for arm64 from source, see the readme for instructions");
+    }
+}
+
+/// Number of log lines kept around for crash reports
+const RECENT_LOG_LINES: usize = 100;
+
+/// Keeps the last [`RECENT_LOG_LINES`] formatted log lines in memory
+#[derive(Clone, Default)]
+struct RecentLogs(Arc<Mutex<VecDeque<String>>>);
+
+impl io::Write for RecentLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut lines = self.0.lock().unwrap();
+        if lines.len() == RECENT_LOG_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(String::from_utf8_lossy(buf).trim_end().to_owned());
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes a json crash report before handing the panic to the previously installed hook.
+/// The report stays local, it is up to the user to attach it to a bug report
+fn install_crash_reporter(path: PathBuf, recent_logs: RecentLogs) {
+    let previous_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned());
+
+        // the panic might have happened while logging, don't wait on the lock
+        let recent_logs = recent_logs
+            .0
+            .try_lock()
+            .map(|lines| lines.clone())
+            .unwrap_or_default();
+
+        let report = serde_json::json!({
+            "message": message,
+            "location": info.location().map(|l| l.to_string()),
+            "backtrace": Backtrace::force_capture().to_string(),
+            "version": env!("CARGO_PKG_VERSION"),
+            "target": env!("BUILD_TARGET"),
+            "recent_logs": recent_logs,
+        });
+
+        // don't bother reporting failures here, we're already crashing
+        if let Ok(file) = File::create(&path) {
+            let _ = serde_json::to_writer_pretty(file, &report);
+            eprintln!("wrote crash report to {}", path.display());
+        }
+
+        previous_hook(info);
+    }));
+}
+
+fn tracing_init(args: &Args) -> RecentLogs {
     let env_filter = EnvFilter::builder()
         .with_default_directive(
             match args.verbose {
@@ -165,30 +784,54 @@ fn tracing_init(args: &Args) {
 
     let subscriber = subscriber.with(file_log);
 
+    let recent_logs = RecentLogs::default();
+    let recent_log = args.crash_report.is_some().then(|| {
+        let recent_logs = recent_logs.clone();
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(move || recent_logs.clone())
+            .with_filter(tracing::level_filters::LevelFilter::DEBUG)
+    });
+
+    let subscriber = subscriber.with(recent_log);
+
     tracing::subscriber::set_global_default(subscriber).expect("unable to set up logging");
+
+    recent_logs
 }
 
 #[instrument(skip_all)]
-fn file_capture<E>(args: &Args, mut exporter: E, mut sniffer: GameSniffer) -> Option<E::Export>
+fn file_capture<E>(args: &Args, mut exporter: E, keys: HashMap<u32, Vec<u8>>) -> Option<E::Export>
 where
     E: Exporter,
 {
+    let mut sniffer = GameSniffer::new().set_initial_keys(keys.clone());
     let mut capture =
         pcap::Capture::from_file(args.pcap.as_ref().unwrap()).expect("could not read pcap file");
 
-    capture.filter(PACKET_FILTER, false).unwrap();
+    capture.filter(&args.packet_filter(), false).unwrap();
 
     let mut invalid = 0;
+    let mut too_short = 0;
 
     info!("capturing");
     while let Ok(packet) = capture.next_packet() {
-        if let Some(GamePacket::Commands(commands)) = sniffer.receive_packet(packet.data.to_vec()) {
+        if !long_enough(packet.data) {
+            too_short += 1;
+            continue;
+        }
+
+        let Ok(game_packet) = receive_packet(&mut sniffer, &keys, packet.data.to_vec()) else {
+            continue;
+        };
+
+        if let Some(GamePacket::Commands(commands)) = game_packet {
             if commands.is_empty() {
                 invalid += 1;
 
                 // FIXME: disable the invalid packet checks until the situation in
                 // reliquary lib has been resolved
-                
+
                 // if invalid >= 50 {
                 //     error!("received 50 packets that could not be segmented");
                 //     warn!("you probably started capturing when you were already in-game");
@@ -210,29 +853,89 @@ where
         }
     }
 
+    debug!(too_short, "dropped packets that were too short");
+
     Some(exporter.export())
 }
 
 #[instrument(skip_all)]
-fn live_capture<E>(args: &Args, mut exporter: E, mut sniffer: GameSniffer) -> Option<E::Export>
+fn live_capture<E>(args: &Args, mut exporter: E, keys: HashMap<u32, Vec<u8>>) -> Option<E::Export>
 where
     E: Exporter,
 {
+    let mut sniffer = GameSniffer::new().set_initial_keys(keys.clone());
     let (tx, rx) = mpsc::channel();
     let mut join_handles = Vec::new();
 
+    // tells the capture threads to stop. pcap reads block on idle devices, so the threads
+    // read in non-blocking mode and check this in between instead of waiting for a packet
+    let abort = Arc::new(AtomicBool::new(false));
+
+    {
+        let abort = abort.clone();
+        let result = ctrlc::set_handler(move || {
+            // once capturing is over (or on a second ctrl-c), exit as usual
+            if abort.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+        });
+
+        if let Err(e) = result {
+            warn!(%e, "could not set up ctrl-c handler");
+        }
+    }
+
+    let all_devices = match Device::list() {
+        Ok(devices) if !devices.is_empty() => devices,
+        Ok(_) => {
+            error!("could not find any network devices");
+            warn_no_devices();
+            return None;
+        }
+        Err(e) => {
+            error!(%e, "could not list network devices");
+            warn_no_devices();
+            return None;
+        }
+    };
+
+    let mut vpn_devices = Vec::new();
+
     // we need to specify a specific network device when using pcap to capture network packets.
     // to lessen the burden on the user, we instead just capture *all* valid network devices
     // by capturing each on a different thread and sending the captured packets to a mpsc channel
-    for device in Device::list()
-        .unwrap()
-        .into_iter()
-        .filter(|d| matches!(d.flags.connection_status, ConnectionStatus::Connected))
-        .filter(|d| !d.addresses.is_empty())
-        .filter(|d| !d.flags.is_loopback())
-    {
+    let devices: Vec<Device> = match &args.device {
+        Some(selector) => match find_device(all_devices, selector) {
+            Some(device) => {
+                info!(device = %device.name, "only capturing on the selected device");
+                vec![device]
+            }
+            None => {
+                error!(
+                    selector,
+                    "could not find network device, see --list-devices"
+                );
+                return None;
+            }
+        },
+        None => all_devices
+            .into_iter()
+            .filter(|d| matches!(d.flags.connection_status, ConnectionStatus::Connected))
+            .filter(|d| !d.addresses.is_empty())
+            .filter(|d| !d.flags.is_loopback())
+            .collect(),
+    };
+
+    for device in devices {
+        if is_likely_vpn(&device) {
+            debug!(device = %device.name, "device looks like a vpn adapter");
+            vpn_devices.push(device.desc.clone().unwrap_or_else(|| device.name.clone()));
+        }
+
         let tx = tx.clone();
-        let handle = std::thread::spawn(move || capture_device(device, tx));
+        let filter = args.packet_filter();
+        let abort = abort.clone();
+        let handle = std::thread::spawn(move || capture_device(device, &filter, tx, &abort));
         join_handles.push(handle);
     }
 
@@ -242,16 +945,59 @@ where
     // have dropped theirs
     drop(tx);
 
+    let mut savefile = args.save_capture.as_deref().and_then(create_savefile);
+
     let mut invalid = 0;
     // let mut warning_sent = false;
 
+    let mut health = CaptureHealth::default();
+    let mut too_short = 0;
+
     info!("instructions: go to main menu screen and go to the \"Click to Start\" screen");
     info!("listening with a timeout of {} seconds...", args.timeout);
+    if args.keep_alive {
+        info!("keeping the capture alive, press ctrl-c to stop listening and write the output");
+    }
+
+    let timeout = Duration::from_secs(args.timeout);
+    let mut last_received = Instant::now();
 
     'recv: loop {
-        match rx.recv_timeout(Duration::from_secs(args.timeout)) {
-            Ok(data) => {
-                match sniffer.receive_packet(data.to_vec()) {
+        if abort.load(Ordering::SeqCst) {
+            info!("interrupted, stop listening");
+            break;
+        }
+
+        if health.login_missed(Instant::now()) {
+            warn!("receiving game packets, but the login was not captured");
+            warn!("you probably started capturing when you were already in-game");
+            warn!("log out, go back to the \"Click to Start\" screen and log back in");
+        }
+
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok((header, data)) => {
+                last_received = Instant::now();
+
+                if let Some(savefile) = &mut savefile {
+                    savefile.write(&pcap::Packet::new(&header, &data));
+                }
+
+                if !long_enough(&data) {
+                    too_short += 1;
+                    continue;
+                }
+
+                if health.packet(Instant::now(), args.decode_warning_after) {
+                    warn_undecodable(&vpn_devices);
+                }
+
+                let Ok(game_packet) = receive_packet(&mut sniffer, &keys, data) else {
+                    continue;
+                };
+
+                health.game_packet(&game_packet);
+
+                match game_packet {
                     Some(GamePacket::Connection(c)) => {
                         match c {
                             ConnectionPacket::HandshakeEstablished => {
@@ -267,10 +1013,10 @@ where
                     Some(GamePacket::Commands(commands)) => {
                         if commands.is_empty() {
                             invalid += 1;
-                            
+
                             // FIXME: disable the invalid packet checks until the situation in
                             // reliquary lib has been resolved
-                            
+
                             // if invalid >= 100 && !warning_sent {
                             //     error!(
                             //         "received a large number of packets that could not be parsed"
@@ -291,13 +1037,15 @@ where
 
                                 if command.command_id == PlayerLoginFinishScRsp {
                                     info!("detected login end, assume initialization is finished");
-                                    break 'recv;
+                                    if !args.keep_alive {
+                                        break 'recv;
+                                    }
                                 }
 
                                 exporter.read_command(command);
                             }
 
-                            if exporter.is_finished() {
+                            if !args.keep_alive && exporter.is_finished() {
                                 info!("retrieved all relevant packets, stop listening");
                                 break 'recv;
                             }
@@ -306,43 +1054,280 @@ where
                     _ => {}
                 }
             }
+            Err(mpsc::RecvTimeoutError::Timeout) if last_received.elapsed() < timeout => {}
             Err(e) => {
                 warn!(%e);
+                if health.keys_suspect() {
+                    warn_undecodable(&vpn_devices);
+                }
                 break;
             }
         }
     }
 
+    abort.store(true, Ordering::SeqCst);
+    drop(rx);
+
+    for handle in join_handles {
+        if handle.join().is_err() {
+            warn!("a capture thread panicked");
+        }
+    }
+
+    debug!(too_short, "dropped packets that were too short");
+
+    if let (Some(mut savefile), Some(path)) = (savefile, &args.save_capture) {
+        match savefile.flush() {
+            Ok(()) => info!("saved captured packets to {}", path.display()),
+            Err(e) => error!(%e, "could not save captured packets"),
+        }
+    }
+
     Some(exporter.export())
 }
 
+thread_local! {
+    /// Set while a panic would be caught and recovered from, see [`receive_packet`]
+    static RECOVERING_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Malformed or stale packets can make the kcp implementation panic (e.g. with
+/// "attempt to add with overflow"). The sniffer state can't be trusted after that, so the
+/// connection it was tracking is dropped and a fresh sniffer picks up the next one, while
+/// everything that was captured up to that point is kept
+fn receive_packet(
+    sniffer: &mut GameSniffer,
+    keys: &HashMap<u32, Vec<u8>>,
+    data: Vec<u8>,
+) -> Result<Option<GamePacket>, ()> {
+    RECOVERING_PANICS.with(|recovering| recovering.set(true));
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| sniffer.receive_packet(data)));
+    RECOVERING_PANICS.with(|recovering| recovering.set(false));
+
+    result.map_err(|_| {
+        error!("packet processing crashed, dropping the current connection");
+        *sniffer = GameSniffer::new().set_initial_keys(keys.clone());
+    })
+}
+
+/// Panics that are recovered from shouldn't print a crash report or write a crash report file,
+/// since the run continues as usual. Has to be installed after all other panic hooks
+fn install_recovered_panic_hook() {
+    let previous_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        if RECOVERING_PANICS.with(Cell::get) {
+            debug!(%info, "recovering from panic");
+        } else {
+            previous_hook(info);
+        }
+    }));
+}
+
+fn create_savefile(path: &Path) -> Option<Savefile> {
+    // packets from all devices end up in the same file, so the link type can't be taken
+    // from any single device. the dead capture only provides the header of the file
+    let savefile = pcap::Capture::dead(Linktype::ETHERNET).and_then(|c| c.savefile(path));
+
+    match savefile {
+        Ok(savefile) => {
+            info!("saving captured packets to {}", path.display());
+            Some(savefile)
+        }
+        Err(e) => {
+            error!(%e, "could not create capture file {}", path.display());
+            None
+        }
+    }
+}
+
+/// Finds a device by its index as printed by --list-devices, or by its name or description
+fn find_device(devices: Vec<Device>, selector: &str) -> Option<Device> {
+    if let Ok(index) = selector.parse::<usize>() {
+        return devices.into_iter().nth(index);
+    }
+
+    devices
+        .into_iter()
+        .find(|d| d.name == selector || d.desc.as_deref() == Some(selector))
+}
+
+/// Without npcap running, windows doesn't report any devices at all. Elsewhere
+/// devices are usually still listed, so missing privileges are more likely
+fn warn_no_devices() {
+    if cfg!(windows) {
+        warn!("npcap does not seem to be installed or running");
+        warn!("install it from https://npcap.com/#download, then restart the archiver");
+    } else {
+        warn_missing_privileges();
+    }
+}
+
+fn warn_missing_privileges() {
+    // every capture thread can run into this, only explain it once
+    static ONCE: Once = Once::new();
+
+    ONCE.call_once(|| {
+        if cfg!(windows) {
+            warn!("npcap was installed with access restricted to administrators");
+            warn!("run the archiver as administrator, or reinstall npcap without the restriction");
+        } else {
+            warn!("capturing requires root or the CAP_NET_RAW and CAP_NET_ADMIN capabilities");
+            warn!("run the archiver with sudo, or grant the capabilities using setcap");
+        }
+    });
+}
+
+/// Tells apart the usual reasons for a live capture coming up empty, from the traffic seen so far
+#[derive(Default)]
+struct CaptureHealth {
+    /// When the first packet on the game ports arrived
+    first_packet: Option<Instant>,
+    handshake_seen: bool,
+    /// The sniffer can only decode the commands of sessions it received the token of
+    token_seen: bool,
+    /// Whether any command besides the token could be decoded
+    decoded_any: bool,
+    undecoded_packets: u64,
+    login_warning_sent: bool,
+}
+
+impl CaptureHealth {
+    /// Counts a packet on the game ports. Returns true once `warn_after` packets arrived after
+    /// the start of the session without a single command being decoded
+    fn packet(&mut self, now: Instant, warn_after: u64) -> bool {
+        self.first_packet.get_or_insert(now);
+
+        if self.keys_suspect() {
+            self.undecoded_packets += 1;
+            return self.undecoded_packets == warn_after;
+        }
+
+        false
+    }
+
+    fn game_packet(&mut self, packet: &Option<GamePacket>) {
+        match packet {
+            Some(GamePacket::Connection(ConnectionPacket::HandshakeEstablished)) => {
+                self.handshake_seen = true;
+            }
+            Some(GamePacket::Commands(commands)) => {
+                for command in commands {
+                    if command.command_id == PlayerGetTokenScRsp {
+                        self.token_seen = true;
+                    } else {
+                        self.decoded_any = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Seeing the start of the session but nothing that decodes after it means that
+    /// the decryption keys are the problem, see [`warn_undecodable`]
+    fn keys_suspect(&self) -> bool {
+        (self.handshake_seen || self.token_seen) && !self.decoded_any
+    }
+
+    /// Game traffic without ever seeing the start of the session means that the capture was
+    /// started while already logged in, which is the most common cause of empty exports.
+    /// Returns true once, after packets have been coming in for [`LOGIN_WARNING_AFTER`]
+    fn login_missed(&mut self, now: Instant) -> bool {
+        let missed = !self.login_warning_sent
+            && !self.handshake_seen
+            && !self.token_seen
+            && self
+                .first_packet
+                .is_some_and(|first| now.duration_since(first) >= LOGIN_WARNING_AFTER);
+
+        self.login_warning_sent |= missed;
+        missed
+    }
+}
+
+fn warn_undecodable(vpn_devices: &[String]) {
+    warn!("receiving game packets, but none of them could be decoded");
+
+    // a vpn tunneling the game traffic leaves only the handshake visible on the
+    // physical interface, which looks the same as outdated keys from here
+    if vpn_devices.is_empty() {
+        warn!("the decryption keys are likely outdated, check for a newer release");
+    } else {
+        warn!(
+            ?vpn_devices,
+            "found network devices that look like vpn adapters"
+        );
+        warn!("if you are using a vpn, disable it or exclude the game from being tunneled");
+        warn!("otherwise the decryption keys are likely outdated, check for a newer release");
+    }
+}
+
+/// Guesses whether a device is a vpn adapter from its name and description.
+/// Covers TUN/TAP drivers (OpenVPN and most others), WireGuard and its wintun driver,
+/// since those are what consumer vpn clients are built on
+fn is_likely_vpn(device: &Device) -> bool {
+    const VPN_MARKERS: &[&str] = &["vpn", "tun", "tap", "wintun", "wireguard", "wg", "ppp"];
+
+    let name = device.name.to_lowercase();
+    let desc = device.desc.as_deref().unwrap_or_default().to_lowercase();
+
+    VPN_MARKERS.iter().any(|marker| {
+        // device names are short identifiers like tun0 or wg0, so only match their start
+        name.starts_with(marker)
+            || desc
+                .split(|c: char| !c.is_alphanumeric())
+                .any(|word| word.starts_with(marker))
+    })
+}
+
 #[instrument(skip_all, fields(device = device.desc))]
-fn capture_device(device: Device, tx: mpsc::Sender<Vec<u8>>) {
-    let mut capture = pcap::Capture::from_device(device)
+fn capture_device(
+    device: Device,
+    filter: &str,
+    tx: mpsc::Sender<(PacketHeader, Vec<u8>)>,
+    abort: &AtomicBool,
+) {
+    let capture = pcap::Capture::from_device(device)
         .unwrap()
         .immediate_mode(true)
         .promisc(true)
         .timeout(0) // explicitly disable timeout??
         .open()
-        .unwrap();
+        .and_then(|capture| capture.setnonblock());
 
-    capture.filter(PACKET_FILTER, true).unwrap();
+    let mut capture = match capture {
+        Ok(capture) => capture,
+        Err(Error::PcapError(e)) if e.contains("permission") || e.contains("denied") => {
+            error!(e, "not allowed to capture on this device");
+            warn_missing_privileges();
+            return;
+        }
+        Err(e) => {
+            warn!(%e, "could not open device");
+            return;
+        }
+    };
+
+    capture.filter(filter, true).unwrap();
 
     debug!("listening");
 
     let mut has_captured = false;
 
-    loop {
+    while !abort.load(Ordering::SeqCst) {
         match capture.next_packet() {
             Ok(packet) => {
                 trace!("captured packet");
-                if let Err(e) = tx.send(packet.data.to_vec()) {
+                if let Err(e) = tx.send((*packet.header, packet.data.to_vec())) {
                     debug!("channel closed: {e}");
                     break;
                 }
 
                 has_captured = true;
             }
+            // no packet available right now
+            Err(Error::TimeoutExpired) => std::thread::sleep(POLL_INTERVAL / 10),
             Err(e) => {
                 // we only really care about capture errors on devices that we already know
                 // are relevant (have sent packets before) and send those errors on warn level.
@@ -353,19 +1338,113 @@ fn capture_device(device: Device, tx: mpsc::Sender<Vec<u8>>) {
 
                 if !has_captured {
                     debug!(?e);
-                    break;
-                } else if matches!(e, Error::TimeoutExpired) {
-                    // somehow a timeout error can still happen even if i explicitly
-                    // disable the timeout?? why :sob:
-                    debug!(?e);
-                    continue;
                 } else {
                     warn!(?e);
-                    break;
                 }
+                break;
             }
         }
     }
 
     debug!("stop listening");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ethernet, ipv4 and udp headers of a packet sent by the game server,
+    /// followed by `payload`. Checksums are left empty
+    fn udp_frame(payload: &[u8]) -> Vec<u8> {
+        let ip_len = (20 + 8 + payload.len()) as u16;
+        let udp_len = (8 + payload.len()) as u16;
+
+        // destination and source mac, then the ethertype of ipv4
+        let mut frame = vec![0; 12];
+        frame.extend([0x08, 0x00]);
+
+        // version and header length, tos, total length, id, flags, ttl, protocol, checksum
+        frame.extend([0x45, 0x00]);
+        frame.extend(ip_len.to_be_bytes());
+        frame.extend([0, 0, 0, 0, 64, 17, 0, 0]);
+        // source and destination ip
+        frame.extend([10, 0, 0, 1, 192, 168, 0, 2]);
+
+        // source port, destination port, length, checksum
+        frame.extend(23301u16.to_be_bytes());
+        frame.extend(50000u16.to_be_bytes());
+        frame.extend(udp_len.to_be_bytes());
+        frame.extend([0, 0]);
+
+        frame.extend(payload);
+        frame
+    }
+
+    #[test]
+    fn frames_without_room_for_a_handshake_are_too_short() {
+        // ethernet pads short frames to 60 bytes
+        let mut padded = udp_frame(&[0xff; 10]);
+        padded.resize(60, 0);
+        assert!(!long_enough(&padded));
+        assert!(!long_enough(&udp_frame(&[0xff; 19])));
+
+        assert!(long_enough(&udp_frame(&[0xff; 20])));
+    }
+
+    #[test]
+    fn undecodable_traffic_without_a_token_warns_about_a_missed_login() {
+        let keys = HashMap::new();
+        let mut sniffer = GameSniffer::new();
+        let mut health = CaptureHealth::default();
+        let start = Instant::now();
+
+        for _ in 0..10 {
+            assert!(!health.packet(start, 5));
+            if let Ok(game_packet) = receive_packet(&mut sniffer, &keys, udp_frame(&[0xff; 40])) {
+                health.game_packet(&game_packet);
+            }
+        }
+
+        assert!(!health.keys_suspect());
+        assert!(!health.login_missed(start));
+        assert!(health.login_missed(start + LOGIN_WARNING_AFTER));
+        // only warned about once
+        assert!(!health.login_missed(start + LOGIN_WARNING_AFTER * 2));
+    }
+
+    #[test]
+    fn handshake_without_decoded_commands_blames_the_keys() {
+        let mut health = CaptureHealth::default();
+        let start = Instant::now();
+
+        health.packet(start, 3);
+        health.game_packet(&Some(GamePacket::Connection(
+            ConnectionPacket::HandshakeEstablished,
+        )));
+
+        assert!(health.keys_suspect());
+        assert!(!health.packet(start, 3));
+        assert!(!health.packet(start, 3));
+        assert!(health.packet(start, 3));
+        assert!(!health.login_missed(start + LOGIN_WARNING_AFTER));
+    }
+
+    #[test]
+    fn truncated_packets_do_not_panic() {
+        let keys = HashMap::new();
+        let mut sniffer = GameSniffer::new();
+        let frame = udp_frame(&[0xff; 64]);
+
+        // everything that makes it past the length filter has to be handled without the
+        // sniffer panicking
+        for len in 0..=frame.len() {
+            let truncated = &frame[..len];
+            if long_enough(truncated) {
+                assert!(receive_packet(&mut sniffer, &keys, truncated.to_vec()).is_ok());
+            }
+        }
+
+        // the flag must not stay set, or panics elsewhere would be swallowed
+        assert!(!RECOVERING_PANICS.with(Cell::get));
+    }
+}
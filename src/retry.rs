@@ -0,0 +1,53 @@
+//! Retrying of network requests that failed for reasons that might go away on their own.
+//! The build script includes this file as well, so it can only depend on `ureq` and std
+
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const ATTEMPTS: u32 = 5;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Exponential backoff, with some jitter so that parallel requests
+/// don't all hit the server again at the same time
+fn retry_delay(attempt: u32) -> Duration {
+    // no rng at hand in the build script, but the clock's sub-second part is random enough
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos();
+    let jitter = BASE_DELAY.mul_f64(nanos as f64 / 1e9);
+
+    BASE_DELAY * 2u32.pow(attempt - 1) + jitter
+}
+
+/// Network errors, server errors and rate limiting have a chance of going away on their own
+fn is_transient(error: &ureq::Error) -> bool {
+    match error {
+        ureq::Error::Transport(_) => true,
+        ureq::Error::Status(status, _) => *status >= 500 || *status == 429,
+    }
+}
+
+/// Calls `request` until it succeeds, retrying transient errors a few times with
+/// exponential backoff. `on_retry` is called with the error, the number of the failed
+/// attempt and the delay before the next one
+pub fn with_retry<T>(
+    mut request: impl FnMut() -> Result<T, ureq::Error>,
+    mut on_retry: impl FnMut(&ureq::Error, u32, Duration),
+) -> Result<T, ureq::Error> {
+    let mut attempt = 1;
+
+    loop {
+        match request() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < ATTEMPTS && is_transient(&e) => {
+                let delay = retry_delay(attempt);
+                on_retry(&e, attempt, delay);
+                thread::sleep(delay);
+
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}